@@ -0,0 +1,81 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use image::imageops;
+use image::ImageBuffer;
+use image::Rgba;
+use image::RgbaImage;
+
+/// Convert an sRGB channel value to linear light, from 0 to 1.
+pub fn srgb_to_linear(v: u8) -> f32 {
+    let v = v as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a linear light value from 0 to 1 back to an sRGB channel value.
+pub fn linear_to_srgb(v: f32) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    let v = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (v * 255.0).round() as u8
+}
+
+/// Resize `image` to `w`x`h` in linear light rather than in sRGB, which keeps thin bright or
+/// dark details from shifting the brightness of the result. Alpha is resized as is.
+pub fn resize_linear(image: &RgbaImage, w: u32, h: u32, filter: imageops::FilterType) -> RgbaImage {
+    let table: Vec<f32> = (0..=255).map(srgb_to_linear).collect();
+    let linear: ImageBuffer<Rgba<f32>, Vec<f32>> =
+        ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+            let [r, g, b, a] = image.get_pixel(x, y).0;
+            Rgba([
+                table[r as usize],
+                table[g as usize],
+                table[b as usize],
+                a as f32 / 255.0,
+            ])
+        });
+
+    let resized = imageops::resize(&linear, w, h, filter);
+    RgbaImage::from_fn(w, h, |x, y| {
+        let [r, g, b, a] = resized.get_pixel(x, y).0;
+        Rgba([
+            linear_to_srgb(r),
+            linear_to_srgb(g),
+            linear_to_srgb(b),
+            (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+        ])
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for v in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(v)), v);
+        }
+        assert!((srgb_to_linear(188) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_resize_linear() {
+        let mut image = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([255, 255, 255, 255]));
+
+        // Half black and half white is half as much light, which is brighter than sRGB 128
+        let srgb = imageops::resize(&image, 1, 1, imageops::FilterType::Triangle);
+        let linear = resize_linear(&image, 1, 1, imageops::FilterType::Triangle);
+        assert_eq!(srgb.get_pixel(0, 0), &Rgba([128, 128, 128, 255]));
+        assert_eq!(linear.get_pixel(0, 0), &Rgba([188, 188, 188, 255]));
+    }
+}
@@ -0,0 +1,84 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Defaults for command-line options, read from `$XDG_CONFIG_HOME/shotgun/config.toml`.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Output format, like `-f`
+    pub format: Option<String>,
+    /// Quality of lossy formats, like `-q`, ignored for other formats
+    pub quality: Option<u8>,
+    /// Draw the cursor, like `--cursor`
+    pub cursor: Option<bool>,
+    /// Output file used when none is given, instead of a time-stamped name
+    pub filename: Option<String>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(text)
+    }
+}
+
+/// Location of the config file, following the XDG base directory specification.
+pub fn path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("shotgun").join("config.toml"))
+}
+
+/// Read the config file, a missing one being the same as an empty one.
+pub fn load() -> Result<Config, Box<dyn Error>> {
+    let path = match path() {
+        Some(p) => p,
+        None => return Ok(Config::default()),
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok(Config::parse(&text)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "format = \"jpeg\"\n\
+            quality = 90\n\
+            cursor = true\n\
+            filename = \"/tmp/shot.jpg\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                format: Some("jpeg".to_string()),
+                quality: Some(90),
+                cursor: Some(true),
+                filename: Some("/tmp/shot.jpg".to_string()),
+            }
+        );
+
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert!(Config::parse("quality = \"high\"").is_err());
+        assert!(Config::parse("colour = true").is_err());
+        assert!(Config::parse("format = ").is_err());
+    }
+}
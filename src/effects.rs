@@ -0,0 +1,746 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cmp;
+
+use image::imageops;
+use image::Rgba;
+use image::RgbaImage;
+
+use crate::util;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dither {
+    /// 4x4 Bayer matrix, gives a regular cross-hatch pattern
+    Ordered,
+    /// Error diffusion, gives a noisier but more faithful result
+    FloydSteinberg,
+}
+
+impl Dither {
+    pub const NAMES: &'static [&'static str] = &["ordered", "floyd-steinberg"];
+
+    pub fn from_name(name: &str) -> Option<Dither> {
+        match name {
+            "ordered" => Some(Dither::Ordered),
+            "floyd-steinberg" => Some(Dither::FloydSteinberg),
+            _ => None,
+        }
+    }
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Round a channel value to the nearest of `levels + 1` evenly spaced values, scaled back to the
+/// full 0-255 range so that the image still displays correctly.
+fn quantize(v: f32, levels: f32) -> u8 {
+    let step = (v.clamp(0.0, 255.0) * levels / 255.0).round();
+    (step * 255.0 / levels).round() as u8
+}
+
+/// Reduce the color channels of `image` to `bits` bits each, dithering to hide the banding.
+///
+/// Alpha is left alone so that masked areas stay transparent.
+pub fn dither(image: &mut RgbaImage, bits: u8, method: Dither) {
+    assert!((1..=8).contains(&bits), "Invalid bit depth {}", bits);
+    let levels = ((1u32 << bits) - 1) as f32;
+    let step = 255.0 / levels;
+
+    match method {
+        Dither::Ordered => {
+            for (x, y, pixel) in image.enumerate_pixels_mut() {
+                let threshold = BAYER_4X4[y as usize % 4][x as usize % 4];
+                let offset = ((threshold as f32 + 0.5) / 16.0 - 0.5) * step;
+                for c in &mut pixel.0[..3] {
+                    *c = quantize(*c as f32 + offset, levels);
+                }
+            }
+        }
+        Dither::FloydSteinberg => {
+            let (w, h) = image.dimensions();
+            let (w, h) = (w as usize, h as usize);
+            // Error carried over to the current and next rows, per color channel
+            let mut current = vec![[0f32; 3]; w + 2];
+            let mut next = vec![[0f32; 3]; w + 2];
+
+            for y in 0..h {
+                for x in 0..w {
+                    let pixel = image.get_pixel_mut(x as u32, y as u32);
+                    for c in 0..3 {
+                        let old = pixel.0[c] as f32 + current[x + 1][c];
+                        let new = quantize(old, levels);
+                        pixel.0[c] = new;
+
+                        let err = old - new as f32;
+                        current[x + 2][c] += err * 7.0 / 16.0;
+                        next[x][c] += err * 3.0 / 16.0;
+                        next[x + 1][c] += err * 5.0 / 16.0;
+                        next[x + 2][c] += err * 1.0 / 16.0;
+                    }
+                }
+                std::mem::swap(&mut current, &mut next);
+                next.iter_mut().for_each(|e| *e = [0.0; 3]);
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Flip {
+    Horizontal,
+    Vertical,
+}
+
+/// Rotate `image` clockwise by `degrees`, which must be a multiple of 90.
+pub fn rotate(image: RgbaImage, degrees: u16) -> RgbaImage {
+    match degrees % 360 {
+        0 => image,
+        90 => imageops::rotate90(&image),
+        180 => imageops::rotate180(&image),
+        270 => imageops::rotate270(&image),
+        _ => panic!("Cannot rotate by {} degrees", degrees),
+    }
+}
+
+pub fn flip(image: &mut RgbaImage, flip: Flip) {
+    match flip {
+        Flip::Horizontal => imageops::flip_horizontal_in_place(image),
+        Flip::Vertical => imageops::flip_vertical_in_place(image),
+    }
+}
+
+/// Scale `image` to fit within `w`x`h`, centered on a canvas of exactly that size filled with
+/// `background`.
+pub fn fit(image: &RgbaImage, (w, h): (u32, u32), background: Rgba<u8>) -> RgbaImage {
+    let (fit_w, fit_h) = util::fit_size(image.dimensions(), (w, h));
+    let scaled = imageops::resize(image, fit_w, fit_h, imageops::FilterType::Lanczos3);
+
+    let mut canvas = RgbaImage::from_pixel(w, h, background);
+    let placed = util::Rect {
+        x: 0,
+        y: 0,
+        w: fit_w as i32,
+        h: fit_h as i32,
+    }
+    .centered_in(util::Rect {
+        x: 0,
+        y: 0,
+        w: w as i32,
+        h: h as i32,
+    });
+    imageops::replace(&mut canvas, &scaled, placed.x as i64, placed.y as i64);
+    canvas
+}
+
+/// Resize a cursor image to `w`x`h` with `filter`, moving its `hotspot` along.
+pub fn resize_cursor(
+    cursor: &RgbaImage,
+    hotspot: util::Point,
+    (w, h): (u32, u32),
+    filter: imageops::FilterType,
+) -> (RgbaImage, util::Point) {
+    let (old_w, old_h) = cursor.dimensions();
+    let resized = imageops::resize(cursor, w, h, filter);
+    let hotspot = util::Point {
+        x: (hotspot.x as i64 * w as i64 / old_w as i64) as i32,
+        y: (hotspot.y as i64 * h as i64 / old_h as i64) as i32,
+    };
+    (resized, hotspot)
+}
+
+/// Draw grid lines every `spacing` pixels over `image`, going through `origin`. Every
+/// `major`th line is drawn two pixels wide.
+pub fn draw_grid(
+    image: &mut RgbaImage,
+    spacing: u32,
+    major: u32,
+    color: Rgba<u8>,
+    origin: util::Point,
+) {
+    assert!(spacing > 0 && major > 0, "Invalid grid spacing");
+    let spacing = spacing as i32;
+    let major = major as i32;
+
+    // Whether a row or column is on a line, given its distance from the origin
+    let on_line = |d: i32| {
+        let line = d.div_euclid(spacing);
+        match d.rem_euclid(spacing) {
+            0 => true,
+            // The second pixel of major lines
+            1 => spacing > 1 && line.rem_euclid(major) == 0,
+            _ => false,
+        }
+    };
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if on_line(x as i32 - origin.x) || on_line(y as i32 - origin.y) {
+            *pixel = util::blend_over(*pixel, color);
+        }
+    }
+}
+
+/// 3x5 pixel glyphs for window IDs, one row per byte with the leftmost pixel in bit 2.
+const GLYPHS: &[(char, [u8; 5])] = &[
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('a', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('b', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('c', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('d', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('e', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('f', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('x', [0b000, 0b101, 0b010, 0b101, 0b000]),
+];
+
+/// Draw `text` in white on a black box with its top-left corner at `(x, y)`, each font pixel
+/// being `scale` pixels wide. Only hex digits and `x` are supported, other characters are blank.
+pub fn draw_label(image: &mut RgbaImage, x: u32, y: u32, text: &str, scale: u32) {
+    let len = text.chars().count() as u32;
+    let (box_w, box_h) = ((len * 4 + 1) * scale, 7 * scale);
+    let (w, h) = image.dimensions();
+
+    for py in y..cmp::min(y + box_h, h) {
+        for px in x..cmp::min(x + box_w, w) {
+            // Position within the box, in font pixels, accounting for the 1px border
+            let (fx, fy) = ((px - x) / scale, (py - y) / scale);
+            let lit = fx % 4 != 0
+                && (1..6).contains(&fy)
+                && text
+                    .chars()
+                    .nth((fx / 4) as usize)
+                    .and_then(|c| GLYPHS.iter().find(|(g, _)| *g == c))
+                    .is_some_and(|(_, rows)| rows[fy as usize - 1] & (0b100 >> (fx % 4 - 1)) != 0);
+            let v = if lit { 255 } else { 0 };
+            image.put_pixel(px, py, Rgba([v, v, v, 255]));
+        }
+    }
+}
+
+/// Where to place a watermark on the image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+    /// Offset of the top-left corner of the watermark
+    At(util::Point),
+}
+
+impl Position {
+    pub const NAMES: &'static [&'static str] = &[
+        "top-left",
+        "top-right",
+        "bottom-left",
+        "bottom-right",
+        "center",
+    ];
+
+    /// Parse one of [`NAMES`](Position::NAMES), or an `X,Y` offset.
+    pub fn parse(s: &str) -> Option<Position> {
+        match s {
+            "top-left" => Some(Position::TopLeft),
+            "top-right" => Some(Position::TopRight),
+            "bottom-left" => Some(Position::BottomLeft),
+            "bottom-right" => Some(Position::BottomRight),
+            "center" => Some(Position::Center),
+            _ => {
+                let (x, y) = s.split_once(',')?;
+                Some(Position::At(util::Point {
+                    x: x.parse().ok()?,
+                    y: y.parse().ok()?,
+                }))
+            }
+        }
+    }
+}
+
+/// Blend `mark` over `image` at `position`, with its alpha multiplied by `opacity` (0 to 1).
+///
+/// Watermarks larger than the image are scaled down to fit, and offsets are clamped so that the
+/// watermark stays entirely within the image.
+pub fn watermark(image: &mut RgbaImage, mark: &RgbaImage, position: Position, opacity: f32) {
+    let opacity = opacity.clamp(0.0, 1.0);
+    let (w, h) = image.dimensions();
+
+    let mut mark = if mark.width() > w || mark.height() > h {
+        let (mark_w, mark_h) = util::fit_size(mark.dimensions(), (w, h));
+        imageops::resize(mark, mark_w, mark_h, imageops::FilterType::Lanczos3)
+    } else {
+        mark.clone()
+    };
+    for pixel in mark.pixels_mut() {
+        pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+    }
+
+    let (free_x, free_y) = ((w - mark.width()) as i32, (h - mark.height()) as i32);
+    let (x, y) = match position {
+        Position::TopLeft => (0, 0),
+        Position::TopRight => (free_x, 0),
+        Position::BottomLeft => (0, free_y),
+        Position::BottomRight => (free_x, free_y),
+        Position::Center => (free_x / 2, free_y / 2),
+        Position::At(p) => (p.x.clamp(0, free_x), p.y.clamp(0, free_y)),
+    };
+    util::composite_over(image, &mark, x, y);
+}
+
+/// How [`censor`] hides an area.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Censor {
+    /// Gaussian blur with the given sigma
+    Blur(f32),
+    /// Blocks of the given size, each filled with its average color
+    Pixelate(u32),
+}
+
+impl Censor {
+    pub const NAMES: &'static [&'static str] = &["blur", "pixelate"];
+
+    /// Parse `blur` or `pixelate`, optionally followed by `:SIGMA` or `:BLOCK_SIZE`.
+    pub fn parse(s: &str) -> Option<Censor> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+        match name {
+            "blur" => {
+                let sigma = param.map_or(Some(8.0), |p| p.parse().ok())?;
+                (sigma > 0.0 && f32::is_finite(sigma)).then_some(Censor::Blur(sigma))
+            }
+            "pixelate" => {
+                let block = param.map_or(Some(16), |p| p.parse().ok())?;
+                (block > 0).then_some(Censor::Pixelate(block))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Blur or pixelate the part of `image` within `rect`, which may extend past its edges.
+pub fn censor(image: &mut RgbaImage, rect: util::Rect, method: Censor) {
+    let bounds = util::Rect {
+        x: 0,
+        y: 0,
+        w: image.width() as i32,
+        h: image.height() as i32,
+    };
+    let rect = match rect.intersection(bounds) {
+        Some(r) => r,
+        None => return,
+    };
+    let (x, y, w, h) = (rect.x as u32, rect.y as u32, rect.w as u32, rect.h as u32);
+
+    let area = imageops::crop_imm(image, x, y, w, h).to_image();
+    let censored = match method {
+        Censor::Blur(sigma) => imageops::blur(&area, sigma),
+        Censor::Pixelate(block) => {
+            // Averaging down to one pixel per block, then blowing the pixels back up
+            let small = imageops::resize(
+                &area,
+                w.div_ceil(block),
+                h.div_ceil(block),
+                imageops::FilterType::Triangle,
+            );
+            imageops::resize(&small, w, h, imageops::FilterType::Nearest)
+        }
+    };
+    imageops::replace(image, &censored, x as i64, y as i64);
+}
+
+/// Direction in which [`stitch`] lays images out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Horizontal,
+    Vertical,
+}
+
+impl Layout {
+    pub const NAMES: &'static [&'static str] = &["horizontal", "vertical"];
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "horizontal" => Some(Layout::Horizontal),
+            "vertical" => Some(Layout::Vertical),
+            _ => None,
+        }
+    }
+}
+
+/// Put `images` next to each other, left to right or top to bottom, with `gutter` pixels between
+/// them. Images are aligned on their top or left edge, and the gutters and any room left around
+/// smaller images are filled with `background`.
+pub fn stitch(
+    images: &[RgbaImage],
+    layout: Layout,
+    gutter: u32,
+    background: Rgba<u8>,
+) -> RgbaImage {
+    let gutters = gutter * (images.len() as u32).saturating_sub(1);
+    let (w, h) = match layout {
+        Layout::Horizontal => (
+            images.iter().map(|i| i.width()).sum::<u32>() + gutters,
+            images.iter().map(|i| i.height()).max().unwrap_or(0),
+        ),
+        Layout::Vertical => (
+            images.iter().map(|i| i.width()).max().unwrap_or(0),
+            images.iter().map(|i| i.height()).sum::<u32>() + gutters,
+        ),
+    };
+
+    let mut canvas = RgbaImage::from_pixel(w, h, background);
+    let mut offset = 0;
+    for image in images {
+        // Blend rather than copy, so that masked areas show the background as well
+        match layout {
+            Layout::Horizontal => {
+                util::composite_over(&mut canvas, image, offset as i32, 0);
+                offset += image.width() + gutter;
+            }
+            Layout::Vertical => {
+                util::composite_over(&mut canvas, image, 0, offset as i32);
+                offset += image.height() + gutter;
+            }
+        }
+    }
+    canvas
+}
+
+/// Lay `tiles` out in a grid of `columns` columns, with `spacing` pixels around each tile.
+///
+/// Cells are the size of the largest tile, shrunk so that the sheet fits in `max_size` (down to
+/// a single pixel if the spacing takes all the room). Tiles that don't fit their cell are scaled
+/// down, keeping their aspect ratio. If `labels` are given, each one is drawn in the corner of the
+/// corresponding tile.
+pub fn contact_sheet(
+    tiles: &[RgbaImage],
+    labels: Option<&[String]>,
+    columns: u32,
+    spacing: u32,
+    max_size: (u32, u32),
+) -> RgbaImage {
+    assert!(columns > 0, "Contact sheet needs at least one column");
+    let rows = (tiles.len() as u32).div_ceil(columns).max(1);
+
+    let mut cell = (
+        tiles.iter().map(|t| t.width()).max().unwrap_or(1),
+        tiles.iter().map(|t| t.height()).max().unwrap_or(1),
+    );
+    let available = |max: u32, count: u32| {
+        let gaps = spacing.saturating_mul(count.saturating_add(1));
+        (max.saturating_sub(gaps) / count).max(1)
+    };
+    let available = (available(max_size.0, columns), available(max_size.1, rows));
+    if cell.0 > available.0 || cell.1 > available.1 {
+        cell = util::fit_size(cell, (cell.0.min(available.0), cell.1.min(available.1)));
+    }
+
+    let mut sheet = RgbaImage::from_pixel(
+        columns * cell.0 + (columns + 1) * spacing,
+        rows * cell.1 + (rows + 1) * spacing,
+        Rgba([0, 0, 0, 0]),
+    );
+
+    for (i, tile) in tiles.iter().enumerate() {
+        let (col, row) = (i as u32 % columns, i as u32 / columns);
+        let cell_rect = util::Rect {
+            x: (spacing + col * (cell.0 + spacing)) as i32,
+            y: (spacing + row * (cell.1 + spacing)) as i32,
+            w: cell.0 as i32,
+            h: cell.1 as i32,
+        };
+
+        let scaled;
+        let tile = if tile.width() > cell.0 || tile.height() > cell.1 {
+            let (w, h) = util::fit_size(tile.dimensions(), cell);
+            scaled = imageops::resize(tile, w, h, imageops::FilterType::Lanczos3);
+            &scaled
+        } else {
+            tile
+        };
+        let placed = util::Rect {
+            x: 0,
+            y: 0,
+            w: tile.width() as i32,
+            h: tile.height() as i32,
+        }
+        .centered_in(cell_rect);
+        imageops::replace(&mut sheet, tile, placed.x as i64, placed.y as i64);
+
+        if let Some(label) = labels.and_then(|l| l.get(i)) {
+            draw_label(&mut sheet, cell_rect.x as u32, cell_rect.y as u32, label, 2);
+        }
+    }
+
+    sheet
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dither_full_depth_is_lossless() {
+        let image = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8 * 16, y as u8 * 16, 7, 200]));
+        for method in [Dither::Ordered, Dither::FloydSteinberg] {
+            let mut dithered = image.clone();
+            dither(&mut dithered, 8, method);
+            assert_eq!(dithered, image);
+        }
+    }
+
+    #[test]
+    fn test_dither_one_bit() {
+        let image = RgbaImage::from_pixel(16, 16, Rgba([128, 64, 0, 10]));
+        for method in [Dither::Ordered, Dither::FloydSteinberg] {
+            let mut dithered = image.clone();
+            dither(&mut dithered, 1, method);
+
+            assert!(dithered
+                .pixels()
+                .all(|p| p.0[..3].iter().all(|&c| c == 0 || c == 255) && p.0[3] == 10));
+
+            // The average brightness survives
+            let mean =
+                |c: usize| dithered.pixels().map(|p| p.0[c] as f32).sum::<f32>() / (16.0 * 16.0);
+            assert!((mean(0) - 128.0).abs() < 16.0, "{}", mean(0));
+            assert!((mean(1) - 64.0).abs() < 16.0, "{}", mean(1));
+            assert_eq!(mean(2), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_from_name() {
+        for name in Dither::NAMES {
+            assert!(Dither::from_name(name).is_some());
+        }
+        assert_eq!(Dither::from_name("atkinson"), None);
+    }
+
+    #[test]
+    fn test_rotate_and_flip() {
+        // 2x1: red, blue
+        let red = Rgba([255, 0, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        let image = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { red } else { blue });
+
+        let rotated = rotate(image.clone(), 90);
+        assert_eq!(rotated.dimensions(), (1, 2));
+        assert_eq!(rotated.get_pixel(0, 0), &red);
+        let rotated = rotate(image.clone(), 270);
+        assert_eq!(rotated.get_pixel(0, 0), &blue);
+        assert_eq!(rotate(image.clone(), 180).get_pixel(0, 0), &blue);
+        assert_eq!(rotate(image.clone(), 0), image);
+
+        let mut flipped = image.clone();
+        flip(&mut flipped, Flip::Horizontal);
+        assert_eq!(flipped, rotate(image.clone(), 180));
+        flip(&mut flipped, Flip::Vertical);
+        assert_eq!(flipped, rotate(image, 180));
+    }
+
+    #[test]
+    fn test_fit() {
+        let red = Rgba([255, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        let image = RgbaImage::from_pixel(4, 2, red);
+
+        let fitted = fit(&image, (8, 8), white);
+        assert_eq!(fitted.dimensions(), (8, 8));
+        // 8x4 in the middle, padded above and below
+        assert_eq!(fitted.get_pixel(0, 0), &white);
+        assert_eq!(fitted.get_pixel(7, 7), &white);
+        assert_eq!(fitted.get_pixel(0, 2), &red);
+        assert_eq!(fitted.get_pixel(7, 5), &red);
+
+        let fitted = fit(&image, (8, 8), Rgba([0, 0, 0, 0]));
+        assert_eq!(fitted.get_pixel(3, 1), &Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_resize_cursor() {
+        let mut cursor = RgbaImage::new(2, 2);
+        cursor.put_pixel(1, 1, Rgba([255, 255, 255, 255]));
+        let hotspot = util::Point { x: 1, y: 1 };
+
+        let (scaled, hotspot) =
+            resize_cursor(&cursor, hotspot, (6, 6), imageops::FilterType::Nearest);
+        assert_eq!(hotspot, util::Point { x: 3, y: 3 });
+        // Nearest neighbour keeps hard edges
+        assert_eq!(scaled.get_pixel(2, 2), &Rgba([0, 0, 0, 0]));
+        assert_eq!(scaled.get_pixel(3, 3), &Rgba([255, 255, 255, 255]));
+        assert_eq!(scaled.get_pixel(5, 5), &Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn test_draw_grid() {
+        let black = Rgba([0, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        let mut image = RgbaImage::from_pixel(30, 30, black);
+        draw_grid(&mut image, 5, 3, white, util::Point { x: 2, y: 0 });
+
+        let row: Vec<bool> = (0..30).map(|x| image.get_pixel(x, 3) == &white).collect();
+        let expected: Vec<bool> = (0..30)
+            .map(|x| [2, 3, 7, 12, 17, 18, 22, 27].contains(&x))
+            .collect();
+        assert_eq!(row, expected);
+        // Horizontal lines from y = 0, the major one is at 0 and 1
+        assert!((0..30).all(|x| image.get_pixel(x, 0) == &white));
+        assert!((0..30).all(|x| image.get_pixel(x, 1) == &white));
+        assert!((0..30).all(|x| image.get_pixel(x, 5) == &white));
+        assert_eq!(image.get_pixel(0, 6), &black);
+    }
+
+    #[test]
+    fn test_contact_sheet() {
+        let red = RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]));
+        let blue = RgbaImage::from_pixel(20, 30, Rgba([0, 0, 255, 255]));
+        let tiles = [red.clone(), blue, red];
+
+        let sheet = contact_sheet(&tiles, None, 2, 5, (1000, 1000));
+        // 2x2 cells of 40x30, with 5px around each
+        assert_eq!(sheet.dimensions(), (2 * 40 + 3 * 5, 2 * 30 + 3 * 5));
+        // The first tile is centered vertically in its cell
+        assert_eq!(sheet.get_pixel(5, 5), &Rgba([0, 0, 0, 0]));
+        assert_eq!(sheet.get_pixel(5, 10), &Rgba([255, 0, 0, 255]));
+        // The second one horizontally
+        assert_eq!(sheet.get_pixel(50, 5), &Rgba([0, 0, 0, 0]));
+        assert_eq!(sheet.get_pixel(60, 5), &Rgba([0, 0, 255, 255]));
+        // The last cell is empty
+        assert_eq!(sheet.get_pixel(70, 50), &Rgba([0, 0, 0, 0]));
+
+        // Cells shrink to honor the maximum width
+        let sheet = contact_sheet(&tiles, None, 3, 0, (60, 1000));
+        assert_eq!(sheet.dimensions(), (60, 15));
+        // And the maximum height, however many rows there are
+        let sheet = contact_sheet(&tiles, None, 1, 0, (1000, 45));
+        assert_eq!(sheet.dimensions(), (20, 45));
+    }
+
+    #[test]
+    fn test_draw_label() {
+        let mut image = RgbaImage::new(20, 10);
+        draw_label(&mut image, 1, 1, "1", 1);
+        // Box around the glyph
+        assert_eq!(image.get_pixel(1, 1), &Rgba([0, 0, 0, 255]));
+        assert_eq!(image.get_pixel(6, 8), &Rgba([0, 0, 0, 0]));
+        // Top row of "1" is 010
+        assert_eq!(image.get_pixel(2, 2), &Rgba([0, 0, 0, 255]));
+        assert_eq!(image.get_pixel(3, 2), &Rgba([255, 255, 255, 255]));
+        // Bottom row is 111
+        assert_eq!(image.get_pixel(2, 6), &Rgba([255, 255, 255, 255]));
+
+        // Labels are clipped to the image
+        draw_label(&mut image, 15, 5, "0x1f", 2);
+    }
+
+    #[test]
+    fn test_position_parse() {
+        assert_eq!(Position::parse("top-right"), Some(Position::TopRight));
+        assert_eq!(Position::parse("center"), Some(Position::Center));
+        assert_eq!(
+            Position::parse("10,-4"),
+            Some(Position::At(util::Point { x: 10, y: -4 }))
+        );
+        assert_eq!(Position::parse("middle"), None);
+        assert_eq!(Position::parse("10"), None);
+    }
+
+    #[test]
+    fn test_watermark() {
+        let mut image = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 255]));
+        let mark = RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 255]));
+        watermark(&mut image, &mark, Position::BottomRight, 0.5);
+        assert_eq!(image.get_pixel(9, 9), &Rgba([128, 128, 128, 255]));
+        assert_eq!(image.get_pixel(8, 8), &Rgba([128, 128, 128, 255]));
+        assert_eq!(image.get_pixel(7, 9), &Rgba([0, 0, 0, 255]));
+
+        // Offsets past the edge keep the watermark inside the image
+        let mut image = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 255]));
+        let at = Position::At(util::Point { x: 100, y: -3 });
+        watermark(&mut image, &mark, at, 1.0);
+        assert_eq!(image.get_pixel(9, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(8, 1), &Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(7, 0), &Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_watermark_larger_than_image() {
+        let mut image = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+        let mark = RgbaImage::from_pixel(8, 4, Rgba([255, 255, 255, 255]));
+        watermark(&mut image, &mark, Position::TopLeft, 1.0);
+        assert_eq!(image.get_pixel(3, 1), &Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(0, 2), &Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_stitch() {
+        let red = RgbaImage::from_pixel(2, 3, Rgba([255, 0, 0, 255]));
+        let blue = RgbaImage::from_pixel(4, 1, Rgba([0, 0, 255, 255]));
+        let gray = Rgba([128, 128, 128, 255]);
+
+        let row = stitch(&[red.clone(), blue.clone()], Layout::Horizontal, 1, gray);
+        assert_eq!(row.dimensions(), (7, 3));
+        assert_eq!(row.get_pixel(1, 2), &Rgba([255, 0, 0, 255]));
+        // Gutter, then the blue image, with background below it
+        assert_eq!(row.get_pixel(2, 0), &gray);
+        assert_eq!(row.get_pixel(3, 0), &Rgba([0, 0, 255, 255]));
+        assert_eq!(row.get_pixel(3, 1), &gray);
+
+        let column = stitch(&[red, blue], Layout::Vertical, 0, Rgba([0, 0, 0, 0]));
+        assert_eq!(column.dimensions(), (4, 4));
+        assert_eq!(column.get_pixel(3, 3), &Rgba([0, 0, 255, 255]));
+        assert_eq!(column.get_pixel(3, 0), &Rgba([0, 0, 0, 0]));
+
+        assert_eq!(Layout::from_name("vertical"), Some(Layout::Vertical));
+        assert_eq!(Layout::from_name("diagonal"), None);
+    }
+
+    #[test]
+    fn test_censor() {
+        assert_eq!(Censor::parse("blur"), Some(Censor::Blur(8.0)));
+        assert_eq!(Censor::parse("pixelate:4"), Some(Censor::Pixelate(4)));
+        assert_eq!(Censor::parse("pixelate:0"), None);
+        assert_eq!(Censor::parse("blur:x"), None);
+        assert_eq!(Censor::parse("smudge"), None);
+
+        // Stripes, so that censoring visibly changes them
+        let original = RgbaImage::from_fn(8, 8, |x, _| {
+            let v = if x % 2 == 0 { 0 } else { 255 };
+            Rgba([v, v, v, 255])
+        });
+        let rect = util::Rect {
+            x: 4,
+            y: 4,
+            w: 8,
+            h: 8,
+        };
+        for method in [Censor::Blur(2.0), Censor::Pixelate(4)] {
+            let mut image = original.clone();
+            censor(&mut image, rect, method);
+            for (x, y, pixel) in image.enumerate_pixels() {
+                if x >= 4 && y >= 4 {
+                    assert!((1..255).contains(&pixel[0]), "{:?} at {},{}", method, x, y);
+                } else {
+                    assert_eq!(pixel, original.get_pixel(x, y));
+                }
+            }
+        }
+
+        // Pixelated blocks are a single color
+        let mut image = original.clone();
+        censor(&mut image, rect, Censor::Pixelate(4));
+        assert!(image.pixels().skip(4 * 8).all(|p| p[3] == 255));
+        assert_eq!(image.get_pixel(4, 4), image.get_pixel(7, 7));
+    }
+}
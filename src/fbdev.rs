@@ -0,0 +1,267 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Last resort capture backend reading the Linux framebuffer device directly.
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::os::unix::io::AsRawFd;
+
+use image::Rgba;
+use image::RgbaImage;
+
+use crate::util;
+
+pub const DEFAULT_DEVICE: &str = "/dev/fb0";
+
+// From linux/fb.h
+const FBIOGET_VSCREENINFO: libc::c_ulong = 0x4600;
+const FBIOGET_FSCREENINFO: libc::c_ulong = 0x4602;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bitfield {
+    pub offset: u32,
+    pub length: u32,
+    pub msb_right: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct VarScreenInfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    red: Bitfield,
+    green: Bitfield,
+    blue: Bitfield,
+    transp: Bitfield,
+    nonstd: u32,
+    activate: u32,
+    height: u32,
+    width: u32,
+    accel_flags: u32,
+    pixclock: u32,
+    left_margin: u32,
+    right_margin: u32,
+    upper_margin: u32,
+    lower_margin: u32,
+    hsync_len: u32,
+    vsync_len: u32,
+    sync: u32,
+    vmode: u32,
+    rotate: u32,
+    colorspace: u32,
+    reserved: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct FixScreenInfo {
+    id: [u8; 16],
+    smem_start: libc::c_ulong,
+    smem_len: u32,
+    type_: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: libc::c_ulong,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+/// Layout of the pixels in a framebuffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub bits_per_pixel: u32,
+    pub red: Bitfield,
+    pub green: Bitfield,
+    pub blue: Bitfield,
+    /// Zero length if there is no alpha channel
+    pub transp: Bitfield,
+}
+
+fn ioctl<T>(file: &File, request: libc::c_ulong, arg: &mut T) -> io::Result<()> {
+    // SAFETY: the kernel writes at most size_of::<T>() bytes to arg for these requests
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg as *mut T) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Capture `rect` (in screen coordinates) from the framebuffer device at `path`.
+pub fn capture(path: &str, rect: util::Rect) -> io::Result<RgbaImage> {
+    let mut file = File::open(path)?;
+
+    let mut var = VarScreenInfo::default();
+    ioctl(&file, FBIOGET_VSCREENINFO, &mut var)?;
+    let mut fix = FixScreenInfo::default();
+    ioctl(&file, FBIOGET_FSCREENINFO, &mut fix)?;
+
+    let screen = util::Rect {
+        x: 0,
+        y: 0,
+        w: var.xres as i32,
+        h: var.yres as i32,
+    };
+    if !screen.contains_rect(rect) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "area extends past the {}x{} framebuffer",
+                screen.w, screen.h
+            ),
+        ));
+    }
+
+    let format = PixelFormat {
+        bits_per_pixel: var.bits_per_pixel,
+        red: var.red,
+        green: var.green,
+        blue: var.blue,
+        transp: var.transp,
+    };
+    let bytes_per_pixel = (format.bits_per_pixel / 8) as usize;
+    let stride = fix.line_length as usize;
+
+    // Only read the rows we need, the visible area starts at the panning offset
+    let first_row = var.yoffset as usize + rect.y as usize;
+    let row_start = (var.xoffset as usize + rect.x as usize) * bytes_per_pixel;
+    file.seek(SeekFrom::Start((first_row * stride) as u64))?;
+    let mut data = vec![0; rect.h as usize * stride];
+    file.read_exact(&mut data)?;
+
+    convert(
+        &data[row_start..],
+        rect.w as u32,
+        rect.h as u32,
+        stride,
+        format,
+    )
+    .ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("unsupported pixel format ({} bpp)", format.bits_per_pixel),
+        )
+    })
+}
+
+/// Convert raw framebuffer rows, `stride` bytes apart, to RGBA.
+///
+/// Returns `None` for pixel sizes that aren't a whole number of bytes.
+pub fn convert(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+    format: PixelFormat,
+) -> Option<RgbaImage> {
+    if !matches!(format.bits_per_pixel, 8 | 16 | 24 | 32) {
+        return None;
+    }
+    let bytes_per_pixel = (format.bits_per_pixel / 8) as usize;
+
+    // Scale a channel of any width to 8 bits
+    let channel = |pixel: u32, field: Bitfield| -> u8 {
+        if field.length == 0 {
+            return 0;
+        }
+        let max = (1u32 << field.length) - 1;
+        let v = (pixel >> field.offset) & max;
+        ((v * 255 + max / 2) / max) as u8
+    };
+
+    Some(RgbaImage::from_fn(width, height, |x, y| {
+        let start = y as usize * stride + x as usize * bytes_per_pixel;
+        let pixel = data[start..start + bytes_per_pixel]
+            .iter()
+            .rev()
+            .fold(0u32, |acc, &b| acc << 8 | b as u32);
+        let alpha = if format.transp.length == 0 {
+            255
+        } else {
+            channel(pixel, format.transp)
+        };
+        Rgba([
+            channel(pixel, format.red),
+            channel(pixel, format.green),
+            channel(pixel, format.blue),
+            alpha,
+        ])
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn field(offset: u32, length: u32) -> Bitfield {
+        Bitfield {
+            offset,
+            length,
+            msb_right: 0,
+        }
+    }
+
+    #[test]
+    fn test_convert_xrgb8888() {
+        let format = PixelFormat {
+            bits_per_pixel: 32,
+            red: field(16, 8),
+            green: field(8, 8),
+            blue: field(0, 8),
+            transp: field(0, 0),
+        };
+        // Two pixels per row plus padding, stored little-endian as BGRX
+        let data = [
+            0x30, 0x20, 0x10, 0x00, 0xff, 0xff, 0xff, 0x00, 0xaa, 0xaa, //
+            0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0x00, 0x00, 0xaa, 0xaa,
+        ];
+        let image = convert(&data, 2, 2, 10, format).unwrap();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0x10, 0x20, 0x30, 255]));
+        assert_eq!(image.get_pixel(1, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(0, 1), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(1, 1), &Rgba([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn test_convert_rgb565() {
+        let format = PixelFormat {
+            bits_per_pixel: 16,
+            red: field(11, 5),
+            green: field(5, 6),
+            blue: field(0, 5),
+            transp: field(0, 0),
+        };
+        let data = 0xf81fu16.to_le_bytes();
+        let image = convert(&data, 1, 1, 2, format).unwrap();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 255, 255]));
+    }
+
+    #[test]
+    fn test_convert_rejects_odd_sizes() {
+        let format = PixelFormat {
+            bits_per_pixel: 12,
+            red: field(8, 4),
+            green: field(4, 4),
+            blue: field(0, 4),
+            transp: field(0, 0),
+        };
+        assert!(convert(&[0; 4], 1, 1, 2, format).is_none());
+    }
+}
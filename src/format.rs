@@ -0,0 +1,858 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+
+use image::codecs;
+use image::RgbImage;
+use image::Rgba;
+use image::RgbaImage;
+
+use crate::util;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Pam,
+    Tiff,
+    Jpeg,
+    Webp,
+    Avif,
+    Qoi,
+    Gif,
+    Raw,
+    Svg,
+}
+
+pub struct FormatInfo {
+    pub format: Format,
+    /// Name accepted by `-f`, also used as the extension of default file names
+    pub name: &'static str,
+    /// Other names accepted by `-f`
+    pub aliases: &'static [&'static str],
+    pub mime_type: &'static str,
+    /// Whether the format takes a `--quality` setting
+    pub has_quality: bool,
+    /// Whether support for this format was compiled in
+    pub available: bool,
+    /// Cargo feature that enables support for this format, if it is optional
+    pub feature: Option<&'static str>,
+}
+
+/// Every output format shotgun knows about, in the order they are listed to users.
+pub const FORMATS: &[FormatInfo] = &[
+    FormatInfo {
+        format: Format::Png,
+        name: "png",
+        aliases: &[],
+        mime_type: "image/png",
+        has_quality: false,
+        available: true,
+        feature: None,
+    },
+    FormatInfo {
+        format: Format::Pam,
+        name: "pam",
+        aliases: &[],
+        mime_type: "image/x-portable-arbitrarymap",
+        has_quality: false,
+        available: true,
+        feature: None,
+    },
+    FormatInfo {
+        format: Format::Tiff,
+        name: "tiff",
+        aliases: &["tif"],
+        mime_type: "image/tiff",
+        has_quality: false,
+        available: true,
+        feature: None,
+    },
+    FormatInfo {
+        format: Format::Jpeg,
+        name: "jpeg",
+        aliases: &["jpg"],
+        mime_type: "image/jpeg",
+        has_quality: true,
+        available: true,
+        feature: None,
+    },
+    FormatInfo {
+        format: Format::Webp,
+        name: "webp",
+        aliases: &[],
+        mime_type: "image/webp",
+        has_quality: true,
+        available: cfg!(feature = "webp"),
+        feature: Some("webp"),
+    },
+    FormatInfo {
+        format: Format::Avif,
+        name: "avif",
+        aliases: &[],
+        mime_type: "image/avif",
+        has_quality: true,
+        available: cfg!(feature = "avif"),
+        feature: Some("avif"),
+    },
+    FormatInfo {
+        format: Format::Qoi,
+        name: "qoi",
+        aliases: &[],
+        mime_type: "image/qoi",
+        has_quality: false,
+        available: true,
+        feature: None,
+    },
+    FormatInfo {
+        format: Format::Gif,
+        name: "gif",
+        aliases: &[],
+        mime_type: "image/gif",
+        has_quality: true,
+        available: true,
+        feature: None,
+    },
+    FormatInfo {
+        format: Format::Raw,
+        name: "raw",
+        aliases: &["rgba"],
+        mime_type: "application/octet-stream",
+        has_quality: false,
+        available: true,
+        feature: None,
+    },
+    FormatInfo {
+        format: Format::Svg,
+        name: "svg",
+        aliases: &[],
+        mime_type: "image/svg+xml",
+        has_quality: false,
+        available: true,
+        feature: None,
+    },
+];
+
+/// Provenance of a capture, for `--metadata`.
+#[derive(Copy, Clone, Debug)]
+pub struct CaptureInfo {
+    /// Unix timestamp of the capture
+    pub timestamp: i64,
+    /// Window that was captured, the root window for the screen
+    pub window: u32,
+    /// Version of shotgun
+    pub version: &'static str,
+}
+
+/// Tuning knobs for the encoders, each of them only applies to some formats.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EncodeOptions {
+    /// Quality of lossy formats, from 1 to 100
+    pub quality: Option<u8>,
+    /// Speed of the AVIF encoder, from 0 (slowest, smallest output) to 10 (fastest)
+    pub avif_speed: Option<u8>,
+    /// Screen geometry of the capture, stored in a `tEXt` chunk of PNG output
+    pub geometry: Option<util::Rect>,
+    /// Where the capture comes from, stored in `tEXt` chunks of PNG output
+    pub capture_info: Option<CaptureInfo>,
+    /// Write PAM output as `TUPLTYPE RGB`, dropping the alpha channel
+    pub pam_rgb: bool,
+    /// Compression level of PNG output, from 0 to 9, mapped to a [`PngCompression`] preset
+    pub png_level: Option<u8>,
+    /// Compression preset of PNG output, takes precedence over `png_level`
+    pub png_compression: Option<PngCompression>,
+    /// Prefix raw output with [`RAW_MAGIC`], the width and the height
+    pub raw_header: bool,
+    /// Color that transparent areas are flattened onto in formats without alpha (black if unset)
+    pub background: Option<Rgba<u8>>,
+    /// Flatten onto `background` in every format, writing RGB where the format allows it
+    pub no_alpha: bool,
+    /// Pixel density stored in PNG (`pHYs`) and JPEG (JFIF) output, in dots per inch
+    pub dpi: Option<u16>,
+}
+
+/// Speed and size tradeoffs of the PNG encoder.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PngCompression {
+    /// Fastest deflate and the cheapest useful filter, for rapid scripted captures
+    Fast,
+    Default,
+    /// Smallest files, several times slower than `Fast`
+    Best,
+}
+
+impl PngCompression {
+    pub const NAMES: &'static [&'static str] = &["fast", "default", "best"];
+
+    pub fn parse(name: &str) -> Option<PngCompression> {
+        match name {
+            "fast" => Some(PngCompression::Fast),
+            "default" => Some(PngCompression::Default),
+            "best" => Some(PngCompression::Best),
+            _ => None,
+        }
+    }
+
+    /// Map a zlib level to a preset, as the PNG encoder doesn't take numeric levels: 0-2 are
+    /// fast, 3-6 default, 7-9 best.
+    fn from_level(level: Option<u8>) -> PngCompression {
+        match level {
+            Some(0..=2) => PngCompression::Fast,
+            Some(7..=9) => PngCompression::Best,
+            _ => PngCompression::Default,
+        }
+    }
+}
+
+/// Compression and filter settings of the PNG encoder for `options`.
+fn png_settings(
+    options: &EncodeOptions,
+) -> (codecs::png::CompressionType, codecs::png::FilterType) {
+    use codecs::png::{CompressionType, FilterType};
+
+    match options
+        .png_compression
+        .unwrap_or_else(|| PngCompression::from_level(options.png_level))
+    {
+        // Trying every filter on each row costs more than fast deflate itself, and on
+        // screenshots Sub alone compresses about as well
+        PngCompression::Fast => (CompressionType::Fast, FilterType::Sub),
+        PngCompression::Default => (CompressionType::Default, FilterType::Adaptive),
+        PngCompression::Best => (CompressionType::Best, FilterType::Adaptive),
+    }
+}
+
+fn png_encoder<W: io::Write>(writer: W, options: &EncodeOptions) -> codecs::png::PngEncoder<W> {
+    let (compression, filter) = png_settings(options);
+    codecs::png::PngEncoder::new_with_quality(writer, compression, filter)
+}
+
+/// Start of the header of raw output, followed by the width and height as little-endian `u32`s.
+pub const RAW_MAGIC: &[u8; 8] = b"SGRGBA8\0";
+
+/// Speed of the quantizer used for GIF palettes, from 1 to 30. The slowest setting samples every
+/// pixel, which takes seconds on large screens for little gain.
+const GIF_SPEED: i32 = 10;
+
+/// Size of the GIF palette for a quality from 1 to 100, leaving room for the transparent color.
+fn gif_colors(quality: u8) -> usize {
+    (quality as usize * 256 / 100).clamp(2, 255)
+}
+
+/// Quantize the opaque pixels of `image` down to `colors` colors.
+fn reduce_colors(image: &mut RgbaImage, colors: usize) {
+    let quantizer = color_quant::NeuQuant::new(GIF_SPEED, colors, image.as_raw());
+    for pixel in image.pixels_mut().filter(|p| p[3] != 0) {
+        quantizer.map_pixel(&mut pixel.0);
+    }
+}
+
+/// Write `image` with `encoder`, or its flattened version if there is one.
+fn write_maybe_flat(
+    image: &RgbaImage,
+    flat: Option<&RgbImage>,
+    encoder: impl image::ImageEncoder,
+) -> image::ImageResult<()> {
+    match flat {
+        Some(rgb) => util::write_image_buffer_with_encoder(rgb, encoder),
+        None => util::write_image_buffer_with_encoder(image, encoder),
+    }
+}
+
+/// `tEXt` keyword under which the capture geometry is stored in PNG files.
+const GEOMETRY_KEYWORD: &str = "shotgun:geometry";
+/// `tEXt` keyword under which the captured window is stored in PNG files.
+const WINDOW_KEYWORD: &str = "shotgun:window";
+
+/// Find a format by its name or one of its aliases, ignoring case.
+pub fn lookup(name: &str) -> Option<&'static FormatInfo> {
+    let name = name.to_lowercase();
+    FORMATS
+        .iter()
+        .find(|f| f.name == name || f.aliases.contains(&name.as_str()))
+}
+
+impl Format {
+    pub fn info(self) -> &'static FormatInfo {
+        FORMATS
+            .iter()
+            .find(|f| f.format == self)
+            .expect("Format missing from the format table")
+    }
+}
+
+pub fn write_image(
+    image: &RgbaImage,
+    format: Format,
+    options: &EncodeOptions,
+    mut writer: impl io::Write,
+) -> image::ImageResult<()> {
+    let background = options.background.unwrap_or(Rgba([0, 0, 0, 255]));
+    let flat = if options.no_alpha {
+        Some(util::flatten(image, background))
+    } else {
+        None
+    };
+    // For the formats that only take RGBA
+    let opaque = flat
+        .as_ref()
+        .map(|rgb| image::DynamicImage::ImageRgb8(rgb.clone()).into_rgba8());
+    let rgba = opaque.as_ref().unwrap_or(image);
+
+    match format {
+        Format::Png => {
+            let mut text = Vec::new();
+            if let Some(geometry) = options.geometry {
+                text.push((GEOMETRY_KEYWORD, geometry.to_string()));
+            }
+            if let Some(info) = options.capture_info {
+                // Keywords from the PNG specification, then our own
+                text.push((
+                    "Creation Time",
+                    format!("{}Z", util::utc_time(info.timestamp)),
+                ));
+                text.push(("Software", format!("shotgun {}", info.version)));
+                text.push((WINDOW_KEYWORD, format!("{:#x}", info.window)));
+            }
+
+            if text.is_empty() && options.dpi.is_none() {
+                write_maybe_flat(image, flat.as_ref(), png_encoder(writer, options))
+            } else {
+                match &flat {
+                    Some(rgb) => write_png_with_chunks(rgb, &text, options, writer),
+                    None => write_png_with_chunks(image, &text, options, writer),
+                }
+            }
+        }
+        Format::Pam => {
+            let encoder = codecs::pnm::PnmEncoder::new(writer)
+                .with_subtype(codecs::pnm::PnmSubtype::ArbitraryMap);
+            // The tuple type follows the pixel type of the buffer
+            if let Some(rgb) = &flat {
+                util::write_image_buffer_with_encoder(rgb, encoder)
+            } else if options.pam_rgb {
+                let rgb = image::DynamicImage::ImageRgba8(image.clone()).into_rgb8();
+                util::write_image_buffer_with_encoder(&rgb, encoder)
+            } else {
+                util::write_image_buffer_with_encoder(image, encoder)
+            }
+        }
+        Format::Tiff => {
+            // The TIFF encoder needs to seek, which stdout can't do
+            let mut buf = io::Cursor::new(Vec::new());
+            let encoder = codecs::tiff::TiffEncoder::new(&mut buf);
+            write_maybe_flat(image, flat.as_ref(), encoder)?;
+            writer.write_all(buf.get_ref())?;
+            Ok(())
+        }
+        Format::Jpeg => {
+            // Same default quality as the image crate
+            let mut encoder =
+                codecs::jpeg::JpegEncoder::new_with_quality(writer, options.quality.unwrap_or(75));
+            if let Some(dpi) = options.dpi {
+                encoder.set_pixel_density(codecs::jpeg::PixelDensity::dpi(dpi));
+            }
+            match flat {
+                Some(rgb) => util::write_image_buffer_with_encoder(&rgb, encoder),
+                None => util::write_image_buffer_with_encoder(
+                    &util::flatten(image, background),
+                    encoder,
+                ),
+            }
+        }
+        #[cfg(feature = "webp")]
+        Format::Webp => {
+            // Lossless unless a quality is given
+            let quality = match options.quality {
+                Some(q) => codecs::webp::WebPQuality::lossy(q),
+                None => codecs::webp::WebPQuality::lossless(),
+            };
+            let encoder = codecs::webp::WebPEncoder::new_with_quality(writer, quality);
+            write_maybe_flat(image, flat.as_ref(), encoder)
+        }
+        #[cfg(not(feature = "webp"))]
+        Format::Webp => unreachable!("WebP support was not compiled in"),
+        #[cfg(feature = "avif")]
+        Format::Avif => {
+            // Same defaults as the image crate (and cavif)
+            let encoder = codecs::avif::AvifEncoder::new_with_speed_quality(
+                writer,
+                options.avif_speed.unwrap_or(4),
+                options.quality.unwrap_or(80),
+            );
+            write_maybe_flat(image, flat.as_ref(), encoder)
+        }
+        #[cfg(not(feature = "avif"))]
+        Format::Avif => unreachable!("AVIF support was not compiled in"),
+        Format::Qoi => write_maybe_flat(image, flat.as_ref(), codecs::qoi::QoiEncoder::new(writer)),
+        Format::Gif => {
+            // GIF has a single fully transparent color, anything partly transparent is opaque
+            let mut image = rgba.clone();
+            for pixel in image.pixels_mut().filter(|p| p[3] != 0) {
+                pixel[3] = 0xFF;
+            }
+            if let Some(quality) = options.quality {
+                reduce_colors(&mut image, gif_colors(quality));
+            }
+            let mut encoder = codecs::gif::GifEncoder::new_with_speed(writer, GIF_SPEED);
+            encoder.encode_frame(image::Frame::new(image))
+        }
+        Format::Raw => {
+            // Rows of RGBA pixels without padding, as the buffer already is
+            if options.raw_header {
+                writer.write_all(RAW_MAGIC)?;
+                writer.write_all(&image.width().to_le_bytes())?;
+                writer.write_all(&image.height().to_le_bytes())?;
+            }
+            writer.write_all(rgba.as_raw())?;
+            Ok(())
+        }
+        Format::Svg => {
+            // Not a vectorization, just a PNG wrapped in an SVG document
+            let mut png = Vec::new();
+            write_maybe_flat(image, flat.as_ref(), png_encoder(&mut png, options))?;
+            let (w, h) = image.dimensions();
+            write!(
+                writer,
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+                    xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+                    width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+                    <image width=\"{w}\" height=\"{h}\" \
+                    xlink:href=\"data:image/png;base64,{}\"/>\n\
+                    </svg>\n",
+                util::base64_encode(&png)
+            )?;
+            Ok(())
+        }
+    }
+}
+
+/// Encode a PNG with extra `tEXt` chunks and a `pHYs` chunk for [`EncodeOptions::dpi`], which
+/// the image crate's encoder can't write.
+fn write_png_with_chunks<P: image::PixelWithColorType<Subpixel = u8>>(
+    image: &image::ImageBuffer<P, Vec<u8>>,
+    text: &[(&str, String)],
+    options: &EncodeOptions,
+    writer: impl io::Write,
+) -> image::ImageResult<()> {
+    let to_image_error = |e| {
+        image::ImageError::Encoding(image::error::EncodingError::new(
+            image::ImageFormat::Png.into(),
+            e,
+        ))
+    };
+
+    let (w, h) = image.dimensions();
+    let mut encoder = png::Encoder::new(writer, w, h);
+    encoder.set_color(match P::COLOR_TYPE {
+        image::ColorType::Rgb8 => png::ColorType::Rgb,
+        _ => png::ColorType::Rgba,
+    });
+    encoder.set_depth(png::BitDepth::Eight);
+    // Same settings as the image crate's encoder, which wraps this one
+    let (compression, filter) = png_settings(options);
+    encoder.set_compression(match compression {
+        codecs::png::CompressionType::Fast => png::Compression::Fast,
+        codecs::png::CompressionType::Best => png::Compression::Best,
+        _ => png::Compression::Default,
+    });
+    match filter {
+        codecs::png::FilterType::Sub => encoder.set_filter(png::FilterType::Sub),
+        _ => encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive),
+    }
+    for (keyword, value) in text {
+        encoder
+            .add_text_chunk(keyword.to_string(), value.clone())
+            .map_err(to_image_error)?;
+    }
+    let mut writer = encoder.write_header().map_err(to_image_error)?;
+    if let Some(dpi) = options.dpi {
+        // Pixels per metre, then the unit (1 for metres)
+        let ppm = (dpi as f64 / 0.0254).round() as u32;
+        let mut phys = Vec::with_capacity(9);
+        phys.extend_from_slice(&ppm.to_be_bytes());
+        phys.extend_from_slice(&ppm.to_be_bytes());
+        phys.push(1);
+        writer
+            .write_chunk(png::chunk::pHYs, &phys)
+            .map_err(to_image_error)?;
+    }
+    writer.write_image_data(image).map_err(to_image_error)?;
+    writer.finish().map_err(to_image_error)
+}
+
+/// Read back the capture geometry stored in a PNG file by `--embed-geometry`.
+pub fn read_png_geometry(reader: impl io::Read) -> Result<Option<util::Rect>, png::DecodingError> {
+    let reader = png::Decoder::new(reader).read_info()?;
+    Ok(reader
+        .info()
+        .uncompressed_latin1_text
+        .iter()
+        .find(|t| t.keyword == GEOMETRY_KEYWORD)
+        .and_then(|t| util::parse_geometry(&t.text)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("png").map(|f| f.format), Some(Format::Png));
+        assert_eq!(lookup("PAM").map(|f| f.format), Some(Format::Pam));
+        assert_eq!(lookup("tif").map(|f| f.format), Some(Format::Tiff));
+        assert!(lookup("bmp").is_none());
+
+        for info in FORMATS {
+            assert_eq!(info.format.info().name, info.name);
+        }
+    }
+
+    #[test]
+    fn test_png_geometry() {
+        let image = RgbaImage::from_pixel(3, 2, image::Rgba([1, 2, 3, 4]));
+        let geometry = util::Rect {
+            x: -1920,
+            y: 40,
+            w: 3,
+            h: 2,
+        };
+        let options = EncodeOptions {
+            geometry: Some(geometry),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_image(&image, Format::Png, &options, &mut out).unwrap();
+        assert_eq!(read_png_geometry(&out[..]).unwrap(), Some(geometry));
+
+        // The pixels are untouched
+        let decoded = image::load_from_memory(&out).unwrap().to_rgba8();
+        assert_eq!(decoded, image);
+
+        let mut out = Vec::new();
+        write_image(&image, Format::Png, &EncodeOptions::default(), &mut out).unwrap();
+        assert_eq!(read_png_geometry(&out[..]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_png_capture_info() {
+        let image = RgbaImage::from_pixel(2, 2, image::Rgba([1, 2, 3, 255]));
+        let geometry = util::Rect {
+            x: 0,
+            y: 0,
+            w: 2,
+            h: 2,
+        };
+        let options = EncodeOptions {
+            geometry: Some(geometry),
+            capture_info: Some(CaptureInfo {
+                timestamp: 1_709_802_300,
+                window: 0x1e00004,
+                version: "1.2.3",
+            }),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_image(&image, Format::Png, &options, &mut out).unwrap();
+
+        let reader = png::Decoder::new(&out[..]).read_info().unwrap();
+        let text: Vec<_> = reader
+            .info()
+            .uncompressed_latin1_text
+            .iter()
+            .map(|t| (t.keyword.as_str(), t.text.as_str()))
+            .collect();
+        assert_eq!(
+            text,
+            [
+                ("shotgun:geometry", "2x2+0+0"),
+                ("Creation Time", "2024-03-07T09:05:00Z"),
+                ("Software", "shotgun 1.2.3"),
+                ("shotgun:window", "0x1e00004"),
+            ]
+        );
+        assert_eq!(read_png_geometry(&out[..]).unwrap(), Some(geometry));
+    }
+
+    #[test]
+    fn test_dpi() {
+        let image = RgbaImage::from_pixel(2, 2, image::Rgba([1, 2, 3, 255]));
+        let options = EncodeOptions {
+            dpi: Some(96),
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        write_image(&image, Format::Png, &options, &mut out).unwrap();
+        let reader = png::Decoder::new(&out[..]).read_info().unwrap();
+        let dims = reader.info().pixel_dims.unwrap();
+        assert_eq!((dims.xppu, dims.yppu), (3780, 3780));
+        assert_eq!(dims.unit, png::Unit::Meter);
+
+        // JFIF APP0 segment right after the SOI marker: density unit 1 (inches), then X and Y
+        let mut out = Vec::new();
+        write_image(&image, Format::Jpeg, &options, &mut out).unwrap();
+        assert_eq!(&out[6..11], b"JFIF\0");
+        assert_eq!(&out[13..18], &[1, 0, 96, 0, 96]);
+    }
+
+    #[test]
+    fn test_no_alpha() {
+        let mut image = RgbaImage::from_pixel(2, 1, image::Rgba([200, 100, 50, 255]));
+        image.put_pixel(1, 0, image::Rgba([0, 0, 0, 0]));
+        let mut options = EncodeOptions {
+            background: Some(image::Rgba([255, 255, 255, 255])),
+            no_alpha: true,
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        write_image(&image, Format::Png, &options, &mut out).unwrap();
+        let decoded = image::load_from_memory(&out).unwrap();
+        assert_eq!(decoded.color(), image::ColorType::Rgb8);
+        assert_eq!(decoded.to_rgb8().as_raw(), &[200, 100, 50, 255, 255, 255]);
+
+        // Also with tEXt chunks, which go through another encoder
+        options.geometry = Some(util::Rect {
+            x: 0,
+            y: 0,
+            w: 2,
+            h: 1,
+        });
+        let mut out = Vec::new();
+        write_image(&image, Format::Png, &options, &mut out).unwrap();
+        let decoded = image::load_from_memory(&out).unwrap();
+        assert_eq!(decoded.color(), image::ColorType::Rgb8);
+        assert_eq!(decoded.to_rgb8().as_raw(), &[200, 100, 50, 255, 255, 255]);
+
+        // Raw output stays RGBA, with opaque pixels
+        let mut out = Vec::new();
+        write_image(&image, Format::Raw, &options, &mut out).unwrap();
+        assert_eq!(out, [200, 100, 50, 255, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_png_level() {
+        // Noisy enough for the compression level to matter
+        let image = RgbaImage::from_fn(64, 64, |x, y| {
+            image::Rgba([(x * y) as u8, (x ^ y) as u8, (x + y * 3) as u8, 255])
+        });
+        let encode = |png_level| {
+            let options = EncodeOptions {
+                png_level,
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            write_image(&image, Format::Png, &options, &mut out).unwrap();
+            out
+        };
+
+        let fast = encode(Some(0));
+        let best = encode(Some(9));
+        assert!(best.len() <= fast.len());
+        assert_eq!(encode(None), encode(Some(6)));
+        for png in [fast, best] {
+            assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8(), image);
+        }
+    }
+
+    #[test]
+    fn test_png_compression() {
+        assert_eq!(PngCompression::parse("best"), Some(PngCompression::Best));
+        assert_eq!(PngCompression::parse("9"), None);
+        for name in PngCompression::NAMES {
+            assert!(PngCompression::parse(name).is_some());
+        }
+
+        let image = RgbaImage::from_fn(64, 64, |x, y| {
+            image::Rgba([(x * y) as u8, (x ^ y) as u8, (x + y * 3) as u8, 255])
+        });
+        let encode = |png_compression, png_level| {
+            let options = EncodeOptions {
+                png_compression,
+                png_level,
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            write_image(&image, Format::Png, &options, &mut out).unwrap();
+            out
+        };
+
+        // Presets take precedence over levels
+        assert_eq!(
+            encode(Some(PngCompression::Fast), Some(9)),
+            encode(None, Some(0))
+        );
+        assert_eq!(
+            encode(Some(PngCompression::Default), None),
+            encode(None, None)
+        );
+        let best = encode(Some(PngCompression::Best), None);
+        assert!(best.len() <= encode(Some(PngCompression::Fast), None).len());
+        assert_eq!(image::load_from_memory(&best).unwrap().to_rgba8(), image);
+    }
+
+    #[test]
+    fn test_png_has_no_metadata() {
+        let image = RgbaImage::new(2, 2);
+        let mut png = Vec::new();
+        write_image(&image, Format::Png, &EncodeOptions::default(), &mut png).unwrap();
+
+        // Walk the chunks after the signature: length, type, data, CRC
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while rest.len() >= 12 {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            chunks.push(String::from_utf8(rest[4..8].to_vec()).unwrap());
+            rest = &rest[12 + len..];
+        }
+        chunks.dedup();
+        assert_eq!(chunks, ["IHDR", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_write_jpeg() {
+        let mut image = RgbaImage::from_pixel(16, 16, Rgba([200, 100, 50, 255]));
+        image.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        let options = EncodeOptions {
+            quality: Some(100),
+            background: Some(Rgba([255, 255, 255, 255])),
+            ..Default::default()
+        };
+        let mut jpeg = Vec::new();
+        write_image(&image, Format::Jpeg, &options, &mut jpeg).unwrap();
+
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!(decoded.color(), image::ColorType::Rgb8);
+        // Lossy, so only roughly the same colors
+        let close =
+            |a: &image::Rgb<u8>, b: [u8; 3]| a.0.iter().zip(b).all(|(&a, b)| a.abs_diff(b) < 16);
+        let decoded = decoded.to_rgb8();
+        assert!(close(decoded.get_pixel(8, 8), [200, 100, 50]));
+        assert!(close(decoded.get_pixel(0, 0), [255, 255, 255]));
+    }
+
+    #[cfg(feature = "webp")]
+    #[test]
+    fn test_write_webp() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 128]));
+        for quality in [None, Some(50)] {
+            let options = EncodeOptions {
+                quality,
+                ..Default::default()
+            };
+            let mut webp = Vec::new();
+            write_image(&image, Format::Webp, &options, &mut webp).unwrap();
+            assert_eq!(&webp[..4], b"RIFF");
+            assert_eq!(&webp[8..12], b"WEBP");
+        }
+    }
+
+    #[test]
+    fn test_write_qoi() {
+        let image = RgbaImage::from_fn(5, 3, |x, y| image::Rgba([x as u8, y as u8, 7, 200]));
+        let mut qoi = Vec::new();
+        write_image(&image, Format::Qoi, &EncodeOptions::default(), &mut qoi).unwrap();
+        assert_eq!(&qoi[..4], b"qoif");
+        assert_eq!(image::load_from_memory(&qoi).unwrap().to_rgba8(), image);
+    }
+
+    #[test]
+    fn test_write_gif() {
+        // A gradient with more colors than a GIF palette holds
+        let mut image = RgbaImage::from_fn(64, 64, |x, y| {
+            image::Rgba([x as u8 * 4, y as u8 * 4, 128, 255])
+        });
+        image.put_pixel(0, 0, image::Rgba([0, 0, 0, 0]));
+
+        let colors = |gif: &[u8]| {
+            let decoded = image::load_from_memory(gif).unwrap().to_rgba8();
+            assert_eq!(decoded.dimensions(), (64, 64));
+            assert_eq!(decoded.get_pixel(0, 0)[3], 0);
+            let mut colors: Vec<_> = decoded.pixels().map(|p| p.0).collect();
+            colors.sort_unstable();
+            colors.dedup();
+            colors.len()
+        };
+
+        let mut gif = Vec::new();
+        write_image(&image, Format::Gif, &EncodeOptions::default(), &mut gif).unwrap();
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert!(colors(&gif) <= 256);
+
+        let options = EncodeOptions {
+            quality: Some(10),
+            ..Default::default()
+        };
+        let mut gif = Vec::new();
+        write_image(&image, Format::Gif, &options, &mut gif).unwrap();
+        assert!(colors(&gif) <= gif_colors(10) + 1);
+    }
+
+    #[test]
+    fn test_write_raw() {
+        let image = RgbaImage::from_fn(3, 2, |x, y| image::Rgba([x as u8, y as u8, 7, 200]));
+        let mut raw = Vec::new();
+        write_image(&image, Format::Raw, &EncodeOptions::default(), &mut raw).unwrap();
+        assert_eq!(raw, image.as_raw()[..]);
+
+        let options = EncodeOptions {
+            raw_header: true,
+            ..Default::default()
+        };
+        let mut raw = Vec::new();
+        write_image(&image, Format::Raw, &options, &mut raw).unwrap();
+        assert_eq!(&raw[..8], RAW_MAGIC);
+        assert_eq!(raw[8..16], [3, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(raw[16..], image.as_raw()[..]);
+    }
+
+    #[test]
+    fn test_write_pam() {
+        let image = RgbaImage::from_pixel(2, 1, image::Rgba([10, 20, 30, 128]));
+
+        // The image crate can't decode RGB_ALPHA PAM, check the data by hand
+        let split_header = |pam: &[u8]| {
+            let end = pam.windows(7).position(|w| w == b"ENDHDR\n").unwrap() + 7;
+            let header = String::from_utf8(pam[..end].to_vec()).unwrap();
+            (header, pam[end..].to_vec())
+        };
+
+        let mut rgba = Vec::new();
+        write_image(&image, Format::Pam, &EncodeOptions::default(), &mut rgba).unwrap();
+        let (header, data) = split_header(&rgba);
+        assert!(header.contains("DEPTH 4\n"));
+        assert!(header.contains("TUPLTYPE RGB_ALPHA\n"));
+        assert_eq!(data, image.as_raw()[..]);
+
+        let options = EncodeOptions {
+            pam_rgb: true,
+            ..Default::default()
+        };
+        let mut rgb = Vec::new();
+        write_image(&image, Format::Pam, &options, &mut rgb).unwrap();
+        let (header, data) = split_header(&rgb);
+        assert!(header.contains("DEPTH 3\n"));
+        assert!(header.contains("TUPLTYPE RGB\n"));
+        assert_eq!(data, [10, 20, 30, 10, 20, 30]);
+        let decoded = image::load_from_memory(&rgb).unwrap();
+        assert_eq!(decoded.color(), image::ColorType::Rgb8);
+        assert_eq!(decoded.to_rgb8().get_pixel(1, 0), &image::Rgb([10, 20, 30]));
+    }
+
+    #[test]
+    fn test_write_svg() {
+        let image = RgbaImage::new(2, 1);
+        let mut out = Vec::new();
+        write_image(&image, Format::Svg, &EncodeOptions::default(), &mut out).unwrap();
+
+        let svg = String::from_utf8(out).unwrap();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains(r#"viewBox="0 0 2 1""#));
+        assert!(svg.contains(
+            r#"<image width="2" height="1" xlink:href="data:image/png;base64,iVBORw0KGgo"#
+        ));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+}
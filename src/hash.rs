@@ -0,0 +1,64 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fmt::Write as _;
+use std::io;
+
+use sha2::Digest;
+pub use sha2::Sha256;
+
+/// Writer that feeds everything written through it into a hasher.
+pub struct HashingWriter<'a, W> {
+    inner: W,
+    hasher: &'a mut Sha256,
+}
+
+impl<'a, W: io::Write> HashingWriter<'a, W> {
+    pub fn new(inner: W, hasher: &'a mut Sha256) -> Self {
+        HashingWriter { inner, hasher }
+    }
+}
+
+impl<W: io::Write> io::Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        // Only hash what actually made it to the inner writer
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Format the digest as lowercase hex, like `sha256sum` does.
+pub fn hex_digest(hasher: Sha256) -> String {
+    hasher.finalize().iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn test_hashing_writer() {
+        let mut hasher = Sha256::default();
+        let mut out = Vec::new();
+        let mut writer = HashingWriter::new(&mut out, &mut hasher);
+        writer.write_all(b"ab").unwrap();
+        writer.write_all(b"c").unwrap();
+
+        assert_eq!(out, b"abc");
+        assert_eq!(
+            hex_digest(hasher),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
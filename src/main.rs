@@ -3,106 +3,2760 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::env;
+use std::error::Error;
+use std::fs;
 use std::fs::File;
 use std::io;
 use std::path::Path;
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
 use std::time;
 
 use getopts::Options;
-use image::codecs;
-use image::GenericImage;
-use image::GenericImageView;
-use image::ImageOutputFormat;
+use image::imageops;
+use image::DynamicImage;
 use image::Rgba;
 use image::RgbaImage;
 use x11rb::protocol::xproto;
 
+mod color;
+mod config;
+mod effects;
+#[cfg(target_os = "linux")]
+mod fbdev;
+mod format;
+#[cfg(feature = "hash")]
+mod hash;
+mod multipage;
+mod timing;
 mod util;
+#[cfg(target_os = "linux")]
+mod wayland;
 mod xwrap;
+use crate::format::Format;
+use crate::xwrap::ChannelOrder;
 use crate::xwrap::Display;
 
+/// Version shown by `-v` and stored by `--metadata`, from git when building from a checkout.
+const VERSION: &str = match option_env!("GIT_VERSION") {
+    Some(v) => v,
+    None => env!("CARGO_PKG_VERSION"),
+};
+
+/// Exit status used when no X server could be reached, from BSD's `sysexits.h`.
+const EX_UNAVAILABLE: i32 = 69;
+/// Exit status used when `--max-time` runs out, same as `timeout(1)`.
+const EX_TIMEOUT: i32 = 124;
+
+/// Longest wait of `--wait-vblank` for Present to notify a vertical blank. Off-screen windows
+/// get a fake one every second.
+const VBLANK_TIMEOUT: time::Duration = time::Duration::from_millis(1100);
+/// Wait of `--wait-vblank` without Present when the refresh rate is unknown, one frame at 60 Hz.
+const VBLANK_FALLBACK_DELAY: time::Duration = time::Duration::from_micros(16_667);
+/// Maximum number of extra captures taken by `--capture-delay-jitter`.
+const TEARING_RETRIES: usize = 5;
+/// Fraction of sampled pixels that may change between two captures without calling it tearing.
+const TEARING_THRESHOLD: f64 = 0.01;
+
+/// Delay between captures while waiting for a black screen to light up.
+const NONBLACK_POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+/// Contact sheets larger than this get their thumbnails scaled down.
+const CONTACT_SHEET_MAX_SIZE: (u32, u32) = (3840, 2160);
+/// Largest `--gutter` between stitched areas.
+const MAX_GUTTER: u32 = 1024;
+/// Largest `--columns` for contact sheets.
+const MAX_COLUMNS: u32 = 64;
+/// Largest `--spacing` for contact sheets.
+const MAX_SPACING: u32 = 256;
+/// Largest factor accepted by `--pointer-scale`.
+const MAX_POINTER_SCALE: u32 = 16;
+/// Largest size in pixels accepted by `--cursor-size`.
+const MAX_CURSOR_SIZE: u32 = 512;
+/// Largest width or height that `--scale`, `--resize` and `--fit` may produce.
+const MAX_IMAGE_SIZE: u32 = 32768;
+/// First wait of `--reconnect` before trying the display again, doubled after each failure.
+const RECONNECT_MIN_DELAY: time::Duration = time::Duration::from_millis(250);
+/// Longest wait of `--reconnect` between two attempts.
+const RECONNECT_MAX_DELAY: time::Duration = time::Duration::from_secs(8);
+/// How often to check for Ctrl-C between the frames of `--count`.
+const INTERRUPT_POLL_INTERVAL: time::Duration = time::Duration::from_millis(50);
+
+/// Set on SIGINT during `--count`, to stop once the current frame is written.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
 fn usage(progname: &str, opts: getopts::Options) {
     let brief = format!("Usage: {progname} [options] [file]");
     let usage = opts.usage(&brief);
     eprint!("{usage}");
 }
 
-fn run() -> i32 {
-    let args: Vec<String> = env::args().collect();
-    let progname = args[0].clone();
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Encoding {
+    Binary,
+    Base64,
+    DataUri,
+}
 
-    let mut opts = Options::new();
-    opts.optopt("i", "id", "Window to capture", "ID");
-    opts.optopt("g", "geometry", "Area to capture", "WxH+X+Y");
-    opts.optopt("f", "format", "Output format", "png/pam");
-    opts.optflag(
-        "s",
-        "single-screen",
-        "Capture the screen determined by the cursor location",
-    );
-    opts.optflag("h", "help", "Print help and exit");
-    opts.optflag("v", "version", "Print version and exit");
+/// Encode the final image into the exact bytes of the output file.
+///
+/// `pages` are the existing pages of a multi-page TIFF, the image is added after them.
+fn encode_output(
+    image: &RgbaImage,
+    pages: &[DynamicImage],
+    format: Format,
+    options: &format::EncodeOptions,
+    encoding: Encoding,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut buf = Vec::new();
+    if pages.is_empty() {
+        format::write_image(image, format, options, &mut buf)?;
+    } else {
+        let mut pages = pages.to_vec();
+        pages.push(DynamicImage::ImageRgba8(image.clone()));
+        multipage::write_tiff_pages(io::Cursor::new(&mut buf), &pages)?;
+    }
 
-    let matches = match opts.parse(&args[1..]) {
-        Ok(m) => m,
-        Err(f) => {
-            eprintln!("{f}");
-            usage(&progname, opts);
+    let prefix = match encoding {
+        Encoding::Binary => return Ok(buf),
+        Encoding::Base64 => String::new(),
+        Encoding::DataUri => format!("data:{};base64,", format.info().mime_type),
+    };
+    let text = format!("{prefix}{}\n", util::base64_encode(&buf));
+    Ok(text.into_bytes())
+}
+
+/// Encode like [`encode_output`], lowering the quality of lossy formats until the output fits in
+/// `limit` bytes. Lossless output that is too large is encoded as `fallback` instead, if given.
+///
+/// Returns the output along with the format and options that produced it.
+fn encode_with_limit(
+    image: &RgbaImage,
+    pages: &[DynamicImage],
+    format: Format,
+    options: &format::EncodeOptions,
+    encoding: Encoding,
+    limit: usize,
+    fallback: Option<Format>,
+) -> Result<(Vec<u8>, Format, format::EncodeOptions), Box<dyn Error + Send + Sync>> {
+    let encoded = encode_output(image, pages, format, options, encoding)?;
+    if encoded.len() <= limit {
+        return Ok((encoded, format, *options));
+    }
+
+    let format = match (format.info().has_quality, fallback) {
+        (true, _) => format,
+        (false, Some(f)) => f,
+        (false, None) => {
+            return Err(format!(
+                "the output is {} bytes, {} is lossless and can't be made smaller",
+                encoded.len(),
+                format.info().name
+            )
+            .into())
+        }
+    };
+
+    let mut best = None;
+    let quality = util::highest_passing(1, options.quality.unwrap_or(100), |quality| {
+        let options = format::EncodeOptions {
+            quality: Some(quality),
+            ..*options
+        };
+        let encoded = encode_output(image, pages, format, &options, encoding)?;
+        let fits = encoded.len() <= limit;
+        if fits {
+            best = Some((encoded, format, options));
+        }
+        Ok::<_, Box<dyn Error + Send + Sync>>(fits)
+    })?;
+    match (quality, best) {
+        (Some(_), Some(best)) => Ok(best),
+        _ => Err(format!("the output doesn't fit in {limit} bytes even at quality 1").into()),
+    }
+}
+
+/// Whether `arg` is a `-.ext` output argument, meaning stdout in the format for `ext`.
+fn is_stdout_with_extension(arg: &str) -> bool {
+    arg.strip_prefix("-.")
+        .is_some_and(|ext| !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Wait out the `-d` or `--delay-ms` delay, if any, counting down for `-d`.
+fn wait_delay(delay: Option<time::Duration>, count_down: bool) {
+    if let Some(delay) = delay {
+        // Only count down for -d, --delay-ms is meant for scripts
+        if count_down && delay >= time::Duration::from_secs(1) {
+            countdown(delay);
+        } else {
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Sleep for `delay`, printing the number of seconds left every second on stderr.
+fn countdown(delay: time::Duration) {
+    let whole = time::Duration::from_secs(delay.as_secs());
+    let fraction = delay - whole;
+
+    // Sleep off the fraction first, so that the rest of the countdown falls on whole seconds
+    let mut left = delay.as_secs();
+    if !fraction.is_zero() {
+        left += 1;
+    }
+    let mut step = if fraction.is_zero() {
+        time::Duration::from_secs(1)
+    } else {
+        fraction
+    };
+    while left > 0 {
+        eprint!("{left}... ");
+        thread::sleep(step);
+        step = time::Duration::from_secs(1);
+        left -= 1;
+    }
+    eprintln!();
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Wait until the X display `name` can be opened again, backing off between attempts. Returns
+/// false if Ctrl-C was pressed first.
+fn wait_for_display(name: Option<&str>) -> bool {
+    let mut delay = RECONNECT_MIN_DELAY;
+    loop {
+        eprintln!(
+            "Lost the X display, trying again in {:.2}s",
+            delay.as_secs_f64()
+        );
+        let start = time::Instant::now();
+        while let Some(left) = delay.checked_sub(start.elapsed()).filter(|l| !l.is_zero()) {
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return false;
+            }
+            thread::sleep(left.min(INTERRUPT_POLL_INTERVAL));
+        }
+        if Display::open(name).is_some() {
+            return true;
+        }
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+}
+
+/// Run `count` captures, starting one every `interval`, until one of them fails or Ctrl-C is
+/// pressed. With `reconnect`, a frame that fails because the display `display_name` went away is
+/// taken again once it is back.
+fn burst(count: u32, interval: time::Duration, reconnect: bool, display_name: Option<&str>) -> i32 {
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+
+    for frame in 1..=count {
+        let start = time::Instant::now();
+        let mut status = run(Some(frame));
+        // Only a display that worked for an earlier frame is waited for, and only if it is the
+        // reason this frame failed
+        while status != 0
+            && reconnect
+            && frame > 1
+            && Display::open(display_name).is_none()
+            && wait_for_display(display_name)
+        {
+            status = run(Some(frame));
+        }
+        if status != 0 {
+            return status;
+        }
+        if frame == count {
+            break;
+        }
+
+        // Wake up regularly so that Ctrl-C doesn't wait for the next frame
+        while !INTERRUPTED.load(Ordering::SeqCst) {
+            match interval.checked_sub(start.elapsed()) {
+                Some(left) if !left.is_zero() => thread::sleep(left.min(INTERRUPT_POLL_INTERVAL)),
+                _ => break,
+            }
+        }
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            eprintln!("Interrupted, stopping after {frame} of {count} frames");
+            break;
+        }
+    }
+    0
+}
+
+/// Format a getopts option name the way it is typed on the command line.
+fn flag_name(opt: &str) -> String {
+    if opt.len() == 1 {
+        format!("-{opt}")
+    } else {
+        format!("--{opt}")
+    }
+}
+
+/// Check that a geometry given with -g fits in `bounds`, explaining which way it doesn't.
+fn geometry_fits(rect: util::Rect, bounds: util::Rect, target: &str) -> bool {
+    let overflow = bounds.overflow(rect);
+    if overflow.is_empty() {
+        return true;
+    }
+    eprintln!("Geometry {rect} doesn't fit in the {target} ({bounds}):");
+    for axis in overflow {
+        eprintln!("  {axis}");
+    }
+    eprintln!("Use --allow-partial to capture the part that does");
+    false
+}
+
+/// Capture `rect` (in screen coordinates) from the Linux framebuffer, for when X fails.
+#[cfg(target_os = "linux")]
+fn grab_fbdev(rect: util::Rect) -> Option<RgbaImage> {
+    let device = env::var("FRAMEBUFFER").unwrap_or_else(|_| fbdev::DEFAULT_DEVICE.to_string());
+    eprintln!("Falling back to reading {device}");
+    match fbdev::capture(&device, rect) {
+        Ok(i) => Some(i),
+        Err(e) => {
+            eprintln!("Failed to capture {device}: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn grab_fbdev(_rect: util::Rect) -> Option<RgbaImage> {
+    eprintln!("--fbdev-fallback is only supported on Linux");
+    None
+}
+
+/// Capture every output of a Wayland compositor, for when there is no X server.
+#[cfg(target_os = "linux")]
+fn grab_wayland(cursor: bool) -> Option<RgbaImage> {
+    match wayland::capture(cursor) {
+        Ok(i) => Some(i),
+        Err(e) => {
+            eprintln!("Failed to capture the Wayland outputs: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn grab_wayland(_cursor: bool) -> Option<RgbaImage> {
+    eprintln!("Wayland capture is only supported on Linux");
+    None
+}
+
+/// Options that work on Wayland, the others need X.
+const WAYLAND_OPTIONS: [&str; 9] = [
+    "display",
+    "format",
+    "quality",
+    "output-spec",
+    "cursor",
+    "no-cursor",
+    "count",
+    "interval",
+    "filename",
+];
+
+/// Expand an output file name template, creating the directories it names, and reporting any
+/// failure on stderr.
+fn expand_filename(
+    template: &str,
+    window: xproto::Window,
+    size: (u32, u32),
+    frame: Option<u32>,
+) -> Option<String> {
+    let now = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |n| n.as_secs() as i64);
+    let local = util::local_time(now).unwrap_or_default();
+    let expanded = util::expand_template(template, local, window, size, frame.unwrap_or(1));
+
+    // Templates can sort screenshots into directories, create them as needed
+    let parent = Path::new(&expanded).parent();
+    if let Some(dir) = parent.filter(|d| !d.as_os_str().is_empty()) {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("Failed to create {}: {e}", dir.display());
+            return None;
+        }
+    }
+    Some(expanded)
+}
+
+/// Names of all the options of `opts`, long ones where they have both.
+fn option_names(opts: &Options) -> Vec<String> {
+    // getopts only exposes its options through the help text, one per line starting with a dash
+    opts.usage("")
+        .lines()
+        .map(str::trim_start)
+        .filter(|l| l.starts_with('-'))
+        .filter_map(|l| {
+            l.split_whitespace()
+                .take_while(|w| w.starts_with('-'))
+                .last()
+                .map(|w| w.trim_start_matches('-').trim_end_matches(',').to_string())
+        })
+        .collect()
+}
+
+/// Take a full screenshot through wlr-screencopy, when there is a compositor but no X server.
+///
+/// Only the options in [`WAYLAND_OPTIONS`] apply, out of all the `options` of shotgun.
+fn run_wayland(
+    options: &[String],
+    matches: &getopts::Matches,
+    config: &config::Config,
+    output: Option<&str>,
+    frame: Option<u32>,
+) -> i32 {
+    for opt in options {
+        if !WAYLAND_OPTIONS.contains(&opt.as_str()) && matches.opt_present(opt) {
+            eprintln!("{} is not supported on Wayland", flag_name(opt));
             return 1;
         }
+    }
+
+    let template = matches
+        .opt_str("filename")
+        .or_else(|| config.filename.clone())
+        .filter(|_| output.is_none());
+    let (output_format, quality) = match output_format(matches, config, template.as_ref()) {
+        Some(f) => f,
+        None => return 1,
     };
+    let mut encode_options = format::EncodeOptions::default();
+    match parse_quality(quality, output_format) {
+        Some(q) => encode_options.quality = q,
+        None => return 1,
+    }
 
-    if matches.opt_present("h") {
-        usage(&progname, opts);
-        return 0;
+    let cursor = !matches.opt_present("no-cursor")
+        && (matches.opt_present("cursor") || config.cursor == Some(true));
+    let image = match grab_wayland(cursor) {
+        Some(i) => i,
+        None => return 1,
+    };
+
+    let encoded = match encode_output(
+        &image,
+        &[],
+        output_format,
+        &encode_options,
+        Encoding::Binary,
+    ) {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("Failed to encode image: {e}");
+            return 1;
+        }
+    };
+    if output_format == Format::Raw {
+        let (w, h) = image.dimensions();
+        eprintln!("Raw RGBA: width {w}, height {h}, stride {} bytes", w * 4);
     }
 
-    // One loose argument allowed (file name)
-    if matches.free.len() > 1 {
-        eprintln!("Too many arguments");
-        usage(&progname, opts);
+    let ts_path;
+    let expanded;
+    let path = match (output, template) {
+        (Some(p), _) => p,
+        (None, Some(t)) => {
+            expanded = match expand_filename(&t, 0, image.dimensions(), frame) {
+                Some(e) => e,
+                None => return 1,
+            };
+            expanded.as_str()
+        }
+        (None, None) => {
+            let now = time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map_or(0, |n| n.as_secs());
+            ts_path = match frame {
+                Some(n) => format!("{now}-{n:04}.{}", output_format.info().name),
+                None => format!("{now}.{}", output_format.info().name),
+            };
+            eprintln!("No output specified, defaulting to {ts_path}");
+            ts_path.as_str()
+        }
+    };
+    let result = if path == "-" {
+        io::Write::write_all(&mut io::stdout(), &encoded)
+    } else {
+        fs::write(path, &encoded)
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to write {path}: {e}");
         return 1;
     }
+    0
+}
 
-    if matches.opt_present("v") {
-        let version = option_env!("GIT_VERSION").unwrap_or(env!("CARGO_PKG_VERSION"));
-        eprintln!("shotgun {version}");
-        return 0;
+/// Pick the output format and quality from --output-spec, -f and -q, the extension of the
+/// output file or the config file, reporting any problem on stderr.
+fn output_format(
+    matches: &getopts::Matches,
+    config: &config::Config,
+    filename_template: Option<&String>,
+) -> Option<(Format, Option<String>)> {
+    // --output-spec is shorthand for -f and -q
+    let (format_name, mut quality) = match matches.opt_str("output-spec") {
+        Some(spec) => {
+            if matches.opt_present("f") || matches.opt_present("q") {
+                eprintln!("Cannot use --output-spec with -f or -q");
+                return None;
+            }
+            match spec.split_once(':') {
+                Some((f, q)) => (f.to_string(), Some(q.to_string())),
+                None => (spec, None),
+            }
+        }
+        None => {
+            // Without -f, go by the extension of the output file, if it has one
+            let extension = matches
+                .free
+                .first()
+                .or(filename_template)
+                .and_then(|p| Path::new(p).extension())
+                .map(|e| e.to_string_lossy().into_owned());
+            let format_name = match (matches.opt_str("f"), extension) {
+                (Some(f), _) => f,
+                (None, Some(ext)) => {
+                    if format::lookup(&ext).is_none() {
+                        let extensions: Vec<&str> = format::FORMATS
+                            .iter()
+                            .flat_map(|f| std::iter::once(&f.name).chain(f.aliases))
+                            .copied()
+                            .collect();
+                        eprintln!("Unsupported file extension .{ext}, use -f to pick a format");
+                        eprintln!("Supported extensions are: {}", extensions.join(", "));
+                        return None;
+                    }
+                    ext
+                }
+                (None, None) => config.format.clone().unwrap_or_else(|| "png".to_string()),
+            };
+            (format_name, matches.opt_str("q"))
+        }
+    };
+
+    let output_format = match format::lookup(&format_name) {
+        Some(f) if f.available => f.format,
+        Some(f) => {
+            eprintln!("Support for {} was not compiled into this build", f.name);
+            if let Some(feature) = f.feature {
+                eprintln!("Rebuild shotgun with `--features {feature}` to enable it");
+            }
+            return None;
+        }
+        None => {
+            eprintln!("Invalid image format specified");
+            return None;
+        }
+    };
+
+    // The configured quality only applies to the formats that have one
+    if quality.is_none() && output_format.info().has_quality {
+        quality = config.quality.map(|q| q.to_string());
+    }
+    Some((output_format, quality))
+}
+
+/// Check a quality given with -q or --output-spec against `format`, reporting any problem on
+/// stderr.
+fn parse_quality(quality: Option<String>, format: Format) -> Option<Option<u8>> {
+    let q = match quality {
+        Some(q) => q,
+        None => return Some(None),
+    };
+    if !format.info().has_quality {
+        eprintln!("Quality is not supported for {}", format.info().name);
+        return None;
+    }
+    match q.parse() {
+        Ok(q @ 1..=100) => Some(Some(q)),
+        _ => {
+            eprintln!("Quality must be an integer between 1 and 100");
+            None
+        }
     }
+}
 
-    let display = match Display::open(None) {
-        Some(d) => d,
+/// Capture `rect` of `window` and convert it, reporting any failure on stderr.
+fn grab_image(
+    display: &Display,
+    window: xproto::Window,
+    rect: util::Rect,
+    timings: &mut timing::Timings,
+) -> Option<RgbaImage> {
+    let image = match timing::measure(&mut timings.get_image, || display.get_image(window, rect)) {
+        Some(i) => i,
         None => {
-            eprintln!("Failed to open display");
-            return 1;
+            eprintln!("Failed to get image from X");
+            return None;
         }
     };
-    let root = display.root();
 
-    let window = match matches.opt_str("i") {
-        Some(s) => match util::parse_int::<xproto::Window>(&s) {
-            Ok(r) => r,
-            Err(_) => {
-                eprintln!("Window ID is not a valid integer");
-                eprintln!("Accepted values are decimal, hex (0x*), octal (0o*) and binary (0b*)");
-                return 1;
+    match timing::measure(&mut timings.convert, || image.into_image_buffer()) {
+        Some(i) => Some(i),
+        None => {
+            eprintln!(
+                "Failed to convert captured framebuffer, \
+                    only RGB565 and 8bpc formats are supported.\n\
+                    See https://github.com/neXromancers/shotgun/issues/35, \
+                    or try --force-bgra or --force-rgba."
+            );
+            None
+        }
+    }
+}
+
+/// Capture the on-screen part of `window`, returning its position relative to the root window.
+fn capture_window(
+    display: &Display,
+    window: xproto::Window,
+    timings: &mut timing::Timings,
+) -> Option<(util::Rect, RgbaImage)> {
+    let root_rect = display.get_window_geometry(display.root())?;
+    let window_rect = display.get_window_geometry(window)?;
+    let rect = window_rect.intersection(root_rect)?;
+    let sel = util::Rect {
+        x: rect.x - window_rect.x,
+        y: rect.y - window_rect.y,
+        w: rect.w,
+        h: rect.h,
+    };
+
+    let image = timing::measure(&mut timings.get_image, || display.get_image(window, sel))?;
+    let image = timing::measure(&mut timings.convert, || image.into_image_buffer())?;
+    Some((rect, image))
+}
+
+/// Find the dialogs of `window`, and their own dialogs, from their `WM_TRANSIENT_FOR`.
+///
+/// `window` may be a window manager frame, whose dialogs point at the client window inside it.
+fn find_transients(display: &Display, window: xproto::Window) -> Option<Vec<xproto::Window>> {
+    let mut family = vec![window];
+    family.extend(display.get_children(window)?);
+    let clients = display.get_client_windows()?;
+
+    let mut transients = Vec::new();
+    loop {
+        let found = transients.len();
+        for &client in &clients {
+            if !family.contains(&client)
+                && display
+                    .get_transient_for(client)
+                    .is_some_and(|parent| family.contains(&parent))
+            {
+                family.push(client);
+                transients.push(client);
             }
-        },
-        None => root,
+        }
+        if transients.len() == found {
+            return Some(transients);
+        }
+    }
+}
+
+/// Parse the command line and take a screenshot, or the given frame of `--count`.
+fn run(frame: Option<u32>) -> i32 {
+    let args: Vec<String> = env::args().collect();
+    let progname = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt(
+        "D",
+        "display",
+        "X display to capture, instead of $DISPLAY",
+        "NAME",
+    );
+    opts.optopt("i", "id", "Window to capture", "ID");
+    opts.optmulti(
+        "g",
+        "geometry",
+        "Area to capture, in pixels or percent of the screen or window (e.g. 50%x50%+25%+25%), \
+            repeat to stitch several areas into one image",
+        "WxH+X+Y",
+    );
+    opts.optflag(
+        "",
+        "allow-partial",
+        "Capture the part of a -g area that is on screen, instead of failing",
+    );
+    let format_names: Vec<&str> = format::FORMATS.iter().map(|f| f.name).collect();
+    opts.optopt("f", "format", "Output format", &format_names.join("/"));
+    opts.optopt("q", "quality", "Quality of lossy formats", "1-100");
+    opts.optopt(
+        "",
+        "output-spec",
+        "Output format and quality at once, e.g. avif:60",
+        "FORMAT[:QUALITY]",
+    );
+    opts.optopt(
+        "",
+        "png-level",
+        "PNG compression level, mapped to the --png-compression presets: 0-2 fast, 3-6 default, \
+            7-9 best (default: 6)",
+        "0-9",
+    );
+    opts.optopt(
+        "",
+        "dpi",
+        "Pixel density to store in PNG and JPEG output, or auto for that of the monitor",
+        "N/auto",
+    );
+    opts.optopt(
+        "",
+        "png-compression",
+        "PNG speed/size preset: fast encodes about 5x faster than default but gives files \
+            several times larger, best gives files about 40% smaller but encodes 2x slower",
+        "fast/default/best",
+    );
+    opts.optflag(
+        "",
+        "raw-header",
+        "Start raw output with a magic string, the width and the height",
+    );
+    opts.optopt(
+        "",
+        "pam-tupltype",
+        "Tuple type of PAM output (default: RGB_ALPHA)",
+        "RGB/RGB_ALPHA",
+    );
+    opts.optmulti(
+        "",
+        "censor",
+        "Blur or pixelate an area of the capture, relative to it (repeatable)",
+        "WxH+X+Y:blur[:SIGMA]/pixelate[:BLOCK]",
+    );
+    opts.optopt(
+        "",
+        "stack",
+        "How areas given by several -g are laid out (default: horizontal)",
+        "horizontal/vertical",
+    );
+    opts.optopt(
+        "",
+        "gutter",
+        "Space between areas given by several -g (default: 0)",
+        "PIXELS",
+    );
+    opts.optopt(
+        "",
+        "background",
+        "Color that transparent areas become in formats without alpha or with --no-alpha \
+            (default: black), of the gutters between areas given by several -g, and of the \
+            padding added by --fit",
+        "COLOR",
+    );
+    opts.optflag(
+        "",
+        "no-alpha",
+        "Flatten transparent areas onto the --background color in every format",
+    );
+    opts.optopt(
+        "",
+        "avif-speed",
+        "AVIF encoder speed, higher is faster but larger (default: 4)",
+        "0-10",
+    );
+    opts.optflag(
+        "s",
+        "single-screen",
+        "Capture the screen determined by the cursor location",
+    );
+    opts.optflag("a", "active", "Capture the window that has the focus");
+    opts.optflag("k", "interactive", "Click the window to capture");
+    opts.optflag(
+        "",
+        "deepest",
+        "With -k, capture the innermost subwindow clicked rather than the top-level window",
+    );
+    opts.optopt(
+        "",
+        "name",
+        "Capture the topmost window whose title or class contains NAME, ignoring case",
+        "NAME",
+    );
+    opts.optflag("", "name-exact", "Match --name exactly, case included");
+    opts.optflag(
+        "",
+        "all-matches",
+        "Fail if several windows match --name, listing them",
+    );
+    opts.optflag("r", "region", "Drag a rectangle over the area to capture");
+    opts.optflag(
+        "",
+        "freeze",
+        "Freeze the screen while selecting with -r, and capture it as it was",
+    );
+    opts.optflag(
+        "",
+        "window-under-cursor",
+        "Capture the top-level window under the cursor",
+    );
+    opts.optopt(
+        "",
+        "xinerama-screen",
+        "Capture a screen by its Xinerama index",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "monitor",
+        "Capture the screen shown on a RandR output, e.g. DP-2",
+        "NAME",
+    );
+    opts.optflag(
+        "",
+        "primary",
+        "Capture the screen shown on the primary RandR output",
+    );
+    opts.optopt(
+        "",
+        "window-stack",
+        "Composite several windows, later ones drawn on top",
+        "ID,ID,...",
+    );
+    opts.optopt(
+        "d",
+        "delay",
+        "Wait SECONDS (may be fractional) before capturing",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "delay-ms",
+        "Wait MS milliseconds before capturing",
+        "MS",
+    );
+    opts.optflag(
+        "",
+        "wait-vblank",
+        "Wait for the next vertical blank before capturing, with the Present extension",
+    );
+    opts.optopt(
+        "",
+        "capture-delay-jitter",
+        "Recapture until two captures MS apart match, to avoid tearing (slow, heuristic)",
+        "MS",
+    );
+    opts.optopt(
+        "",
+        "capture-region-from-window",
+        "Capture an area given relative to a window",
+        "ID:WxH+X+Y",
+    );
+    opts.optopt(
+        "",
+        "span",
+        "Capture the area spanning two windows, masking everything else",
+        "ID1,ID2",
+    );
+    opts.optopt(
+        "",
+        "pixel-at",
+        "Print the color of the pixel at X,Y on the screen (pixels or percent) instead of \
+            capturing",
+        "X,Y",
+    );
+    opts.optflag(
+        "",
+        "pick",
+        "Print the color of the pixel under the cursor, or at the position of -g, instead of \
+            capturing",
+    );
+    opts.optopt(
+        "",
+        "pick-format",
+        "How --pick and --pixel-at print colors (default: hex)",
+        "hex/rgb/rgba",
+    );
+    opts.optopt("", "pixel-format", "Same as --pick-format", "hex/rgb/rgba");
+    opts.optflag("p", "cursor", "Draw the mouse cursor over the capture");
+    opts.optopt(
+        "",
+        "pointer-scale",
+        "Draw the cursor N times larger, with -p",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "cursor-size",
+        "Draw the cursor scaled to fit in N by N pixels, with -p (overrides --pointer-scale)",
+        "N",
+    );
+    opts.optflag(
+        "",
+        "no-cursor",
+        "Leave the cursor out, even if the config file says otherwise",
+    );
+    opts.optflag(
+        "",
+        "wallpaper-only",
+        "Capture the desktop wallpaper, without any windows",
+    );
+    opts.optflag(
+        "",
+        "with-transients",
+        "Also capture the dialogs of the window",
+    );
+    opts.optflag(
+        "",
+        "decorations",
+        "Include the title bar and borders the window manager draws around the window",
+    );
+    opts.optflag(
+        "",
+        "contact-sheet",
+        "Capture every window matching --class into a grid",
+    );
+    opts.optopt("", "class", "Window class for --contact-sheet", "NAME");
+    opts.optopt(
+        "",
+        "columns",
+        "Number of columns of the contact sheet (default: square grid)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "spacing",
+        "Space between contact sheet tiles (default: 8)",
+        "PX",
+    );
+    opts.optflag("", "label", "Label contact sheet tiles with window IDs");
+    opts.optopt(
+        "",
+        "isolate-window",
+        "Make everything outside of a window transparent",
+        "ID",
+    );
+    opts.optflag(
+        "",
+        "wait-nonblack",
+        "Recapture while the screen is all black, e.g. when a monitor is waking up (heuristic)",
+    );
+    opts.optopt(
+        "",
+        "wait-timeout",
+        "Give up waiting for a non-black screen after SEC seconds (default: 5)",
+        "SEC",
+    );
+    opts.optflag(
+        "",
+        "work-area",
+        "Exclude panels and docks (_NET_WORKAREA) from the capture",
+    );
+    opts.optflag(
+        "",
+        "list-windows",
+        "Print the window tree with IDs, geometry, titles and classes, and exit",
+    );
+    opts.optflag("", "all", "Include unmapped windows in --list-windows");
+    opts.optflag(
+        "",
+        "dry-run-list-screens",
+        "Print the layout of the screens and exit",
+    );
+    opts.optflag(
+        "",
+        "capture-pointer-shape-name",
+        "Print the name of the current cursor shape and exit",
+    );
+    opts.optflag(
+        "",
+        "append",
+        "Add the capture as a new page of an existing TIFF file",
+    );
+    opts.optopt(
+        "",
+        "grid",
+        "Draw a grid with lines every SPACING pixels, e.g. 10:#ff000080",
+        "SPACING:COLOR",
+    );
+    opts.optopt(
+        "",
+        "grid-major",
+        "Draw every Nth grid line thicker (default: 10)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "grid-origin",
+        "Point of the image that grid lines go through (default: 0,0)",
+        "X,Y",
+    );
+    opts.optflag(
+        "",
+        "trim-to-content",
+        "Crop away solid-colored borders around the image",
+    );
+    opts.optopt(
+        "",
+        "rotate",
+        "Rotate the image clockwise, or like the screen it is on with auto",
+        "90/180/270/auto",
+    );
+    opts.optopt("", "flip", "Mirror the image", "h/v");
+    opts.optopt(
+        "",
+        "fit",
+        "Scale the image to fit within WxH, padding it to exactly that size with the \
+            --background color (default: transparent)",
+        "WxH",
+    );
+    opts.optopt(
+        "",
+        "diff",
+        "Write the differences with BASELINE instead of the capture",
+        "BASELINE",
+    );
+    opts.optopt(
+        "",
+        "watermark",
+        "Blend an image over the capture, e.g. logo.png:bottom-right:0.5",
+        "FILE:POSITION:OPACITY",
+    );
+    opts.optopt(
+        "",
+        "scale",
+        "Scale the image by PERCENT, e.g. 50%",
+        "PERCENT",
+    );
+    opts.optopt(
+        "",
+        "resize",
+        "Resize the image to WxH, or keep the aspect ratio with Wx0 or 0xH",
+        "WxH",
+    );
+    opts.optopt(
+        "",
+        "scale-filter",
+        "Filter used by --scale and --resize (default: lanczos3)",
+        "nearest/triangle/lanczos3",
+    );
+    opts.optflag(
+        "",
+        "linear-downscale",
+        "Resize in linear light for --scale and --resize, slower but keeps the brightness of \
+            fine details",
+    );
+    opts.optopt(
+        "",
+        "dither",
+        "Reduce colors to BITS bits per channel (1-8), with dithering",
+        "BITS",
+    );
+    opts.optopt(
+        "",
+        "dither-method",
+        &format!(
+            "Dithering algorithm: {} (default: floyd-steinberg)",
+            effects::Dither::NAMES.join("/")
+        ),
+        "NAME",
+    );
+    opts.optflag(
+        "",
+        "render",
+        "Capture through the RENDER extension, may preserve alpha better",
+    );
+    opts.optflag(
+        "",
+        "force-rgba",
+        "Read captured pixels as RGBA bytes, colors are swapped if that is wrong",
+    );
+    opts.optflag(
+        "",
+        "force-bgra",
+        "Read captured pixels as BGRA bytes, colors are swapped if that is wrong",
+    );
+    opts.optflag(
+        "",
+        "fbdev-fallback",
+        "Read the Linux framebuffer if capturing from X fails",
+    );
+    opts.optmulti(
+        "",
+        "also",
+        "Also write the capture to FILE in another format, can be repeated",
+        "FORMAT:FILE",
+    );
+    opts.optopt(
+        "",
+        "limit-bytes",
+        "Lower the quality of lossy formats until the output fits in N bytes",
+        "N",
+    );
+    opts.optflag(
+        "",
+        "allow-lossy-fallback",
+        "With --limit-bytes, switch to a lossy format if the output is too large",
+    );
+    opts.optopt(
+        "",
+        "max-time",
+        "Give up if capturing and encoding take longer than SEC seconds",
+        "SEC",
+    );
+    opts.optflag(
+        "",
+        "timing-json",
+        "Print how long each step took to stderr, as JSON",
+    );
+    opts.optflag(
+        "",
+        "print-hash",
+        "Print the SHA-256 of the output file to stderr",
+    );
+    opts.optopt(
+        "",
+        "count",
+        "Take N screenshots, use with --filename and %n to number them",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "interval",
+        "Time between the screenshots of --count (default: 1)",
+        "SECONDS",
+    );
+    opts.optflag(
+        "",
+        "reconnect",
+        "With --count, wait for the X display to come back if it goes away, instead of stopping",
+    );
+    opts.optopt(
+        "",
+        "filename",
+        "Name the output file after TEMPLATE, with %Y, %m, %d, %H, %M, %S, %wid and %wxh \
+            standing for the date, window ID and size",
+        "TEMPLATE",
+    );
+    opts.optflag(
+        "c",
+        "clipboard",
+        "Copy the image to the clipboard, as well as to the output file if one is given, \
+            staying in the foreground until something else is copied",
+    );
+    opts.optopt(
+        "",
+        "clipboard-timeout",
+        "Stop serving the clipboard after SECONDS",
+        "SECONDS",
+    );
+    opts.optflag("", "base64", "Write the image base64-encoded to stdout");
+    opts.optflag("", "data-uri", "Write the image to stdout as a data: URI");
+    opts.optflag(
+        "",
+        "strip-metadata",
+        "Make sure the output carries no metadata besides the image itself",
+    );
+    opts.optflag(
+        "",
+        "metadata",
+        "Store the capture time, window ID, geometry and shotgun version in the PNG file",
+    );
+    opts.optflag(
+        "",
+        "embed-geometry",
+        "Store the screen geometry of the capture in the PNG file",
+    );
+    opts.optopt(
+        "",
+        "read-geometry",
+        "Print the geometry stored in FILE by --embed-geometry and exit",
+        "FILE",
+    );
+    opts.optflag(
+        "",
+        "probe-formats",
+        "List the output formats supported by this build and exit",
+    );
+    opts.optflag("h", "help", "Print help and exit");
+    opts.optflag("v", "version", "Print version and exit");
+
+    // getopts would take `-.png` for a bundle of short options, set it aside before parsing
+    let (stdout_args, args): (Vec<String>, Vec<String>) = args[1..]
+        .iter()
+        .cloned()
+        .partition(|a| is_stdout_with_extension(a));
+    let mut matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{f}");
+            usage(&progname, opts);
+            return 1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        usage(&progname, opts);
+        return 0;
+    }
+
+    if matches.opt_present("v") {
+        eprintln!("shotgun {VERSION}");
+        return 0;
+    }
+
+    matches.free.extend(stdout_args);
+
+    let config = match config::load() {
+        Ok(c) => c,
+        Err(e) => {
+            let path = config::path().unwrap_or_default();
+            eprintln!("Invalid config file {}: {e}", path.display());
+            return 1;
+        }
+    };
+
+    // One loose argument allowed (file name)
+    if matches.free.len() > 1 {
+        eprintln!("Too many arguments");
+        usage(&progname, opts);
+        return 1;
+    }
+
+    let data_uri = matches.opt_present("data-uri");
+    let base64 = matches.opt_present("base64") || data_uri;
+    // `-.png` and such write to stdout, the extension only picks the format
+    let output = matches.free.first().map(|p| {
+        if is_stdout_with_extension(p) {
+            "-"
+        } else {
+            p.as_str()
+        }
+    });
+    if base64 && output.is_some_and(|p| p != "-") {
+        eprintln!(
+            "--base64 and --data-uri always write to stdout, cannot use them with an output file"
+        );
+        return 1;
+    }
+
+    let clipboard = matches.opt_present("clipboard");
+    // Stdout is taken by `-` and `-.png`, which only pick the format, so only a file can go
+    // along with the clipboard
+    if clipboard && base64 {
+        eprintln!("Cannot use --clipboard with --base64 or --data-uri");
+        return 1;
+    }
+    let clipboard_timeout = match matches.opt_str("clipboard-timeout") {
+        Some(_) if !clipboard => {
+            eprintln!("--clipboard-timeout requires --clipboard");
+            return 1;
+        }
+        Some(secs) => match time::Duration::try_from_secs_f64(secs.parse().unwrap_or(-1.0)) {
+            Ok(d) => Some(d),
+            Err(_) => {
+                eprintln!("Clipboard timeout must be a non-negative number of seconds");
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    if matches.opt_present("probe-formats") {
+        for f in format::FORMATS {
+            let status = if f.available {
+                "supported"
+            } else {
+                "not compiled in"
+            };
+            println!("{}\t{}", f.name, status);
+        }
+        return 0;
+    }
+
+    if let Some(file) = matches.opt_str("read-geometry") {
+        let f = match File::open(&file) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("Failed to open {file}: {e}");
+                return 1;
+            }
+        };
+        match format::read_png_geometry(io::BufReader::new(f)) {
+            Ok(Some(geometry)) => {
+                println!("{geometry}");
+                return 0;
+            }
+            Ok(None) => {
+                eprintln!("{file} does not contain a capture geometry");
+                return 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to read {file} as PNG: {e}");
+                return 1;
+            }
+        }
+    }
+
+    let interval = match matches.opt_str("interval") {
+        Some(_) if !matches.opt_present("count") => {
+            eprintln!("--interval requires --count");
+            return 1;
+        }
+        Some(secs) => match time::Duration::try_from_secs_f64(secs.parse().unwrap_or(-1.0)) {
+            Ok(d) => d,
+            Err(_) => {
+                eprintln!("Interval must be a non-negative number of seconds");
+                return 1;
+            }
+        },
+        None => time::Duration::from_secs(1),
+    };
+    if matches.opt_present("reconnect") && !matches.opt_present("count") {
+        eprintln!("--reconnect requires --count");
+        return 1;
+    }
+    if let Some(count) = matches.opt_str("count") {
+        let count = match count.parse::<u32>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                eprintln!("Count must be a positive integer");
+                return 1;
+            }
+        };
+        if output.is_some_and(|p| p != "-") && count > 1 {
+            eprintln!(
+                "Every frame would overwrite {}, use --filename with %n instead",
+                output.unwrap()
+            );
+            return 1;
+        }
+        // A template without tokens names the same file for every frame too
+        let template = matches
+            .opt_str("filename")
+            .or_else(|| config.filename.clone());
+        if let Some(t) = template.filter(|t| output.is_none() && !base64 && !t.contains('%')) {
+            if count > 1 {
+                eprintln!("Every frame would overwrite {t}, add %n to the file name");
+                return 1;
+            }
+        }
+        if clipboard {
+            eprintln!("Cannot use --count with --clipboard");
+            return 1;
+        }
+        // Each frame goes through run() again, with the same arguments
+        if frame.is_none() {
+            let display_name = matches.opt_str("D");
+            return burst(
+                count,
+                interval,
+                matches.opt_present("reconnect"),
+                display_name.as_deref(),
+            );
+        }
+    }
+
+    let print_hash = matches.opt_present("print-hash");
+    if print_hash && !cfg!(feature = "hash") {
+        eprintln!("--print-hash is not supported by this build");
+        eprintln!("Rebuild shotgun with `--features hash` to enable it");
+        return 1;
+    }
+
+    let mut timings = timing::Timings::default();
+    let display_name = matches.opt_str("D");
+    let mut display =
+        match timing::measure(&mut timings.open, || Display::open(display_name.as_deref())) {
+            Some(d) => d,
+            None => {
+                // Without X, fall back to the compositor unless a display was asked for
+                if display_name.is_none() && env::var_os("WAYLAND_DISPLAY").is_some() {
+                    return run_wayland(&option_names(&opts), &matches, &config, output, frame);
+                }
+                match display_name.or_else(|| env::var("DISPLAY").ok()) {
+                    Some(name) => eprintln!("Failed to open display {name:?}"),
+                    None => eprintln!("Failed to open display ($DISPLAY is not set)"),
+                }
+                return EX_UNAVAILABLE;
+            }
+        };
+    let root = display.root();
+
+    match (
+        matches.opt_present("force-rgba"),
+        matches.opt_present("force-bgra"),
+    ) {
+        (true, true) => {
+            eprintln!("Cannot use --force-rgba and --force-bgra together");
+            return 1;
+        }
+        (true, false) => display.force_channel_order(ChannelOrder::Rgba),
+        (false, true) => display.force_channel_order(ChannelOrder::Bgra),
+        (false, false) => {}
+    }
+
+    if matches.opt_present("render") && !display.enable_render() {
+        eprintln!("The RENDER extension is not available, capturing with GetImage");
+    }
+
+    let delay = match (matches.opt_str("delay-ms"), matches.opt_str("d")) {
+        (Some(_), Some(_)) => {
+            eprintln!("Cannot use -d and --delay-ms together");
+            return 1;
+        }
+        (Some(ms), None) => match ms.parse() {
+            Ok(ms) => Some(time::Duration::from_millis(ms)),
+            Err(_) => {
+                eprintln!("Delay must be a non-negative integer number of milliseconds");
+                return 1;
+            }
+        },
+        (None, Some(secs)) => match time::Duration::try_from_secs_f64(secs.parse().unwrap_or(-1.0))
+        {
+            Ok(d) => Some(d),
+            Err(_) => {
+                eprintln!("Delay must be a non-negative number of seconds");
+                return 1;
+            }
+        },
+        (None, None) => None,
+    };
+
+    // The position to pick at, or None for the cursor, which is only read after the delay
+    let pick = match (matches.opt_str("pixel-at"), matches.opt_present("pick")) {
+        (Some(_), true) => {
+            eprintln!("Cannot use --pick and --pixel-at together");
+            return 1;
+        }
+        (Some(at), false) => Some(Some(at)),
+        (None, true) => Some(matches.opt_str("g")),
+        (None, false) => None,
+    };
+    if let Some(position) = pick {
+        let pixel_format = match (
+            matches.opt_str("pick-format"),
+            matches.opt_str("pixel-format"),
+        ) {
+            (Some(_), Some(_)) => {
+                eprintln!("Cannot use --pick-format and --pixel-format together");
+                return 1;
+            }
+            (f, g) => f.or(g),
+        };
+        let pixel_format = pixel_format.as_deref().unwrap_or("hex");
+        if !["hex", "rgb", "rgba"].contains(&pixel_format) {
+            eprintln!("Invalid pixel format, expected hex, rgb or rgba");
+            return 1;
+        }
+
+        let screen = match display.get_window_geometry(root) {
+            Some(r) => r,
+            None => {
+                eprintln!("Failed to get window geometry");
+                return 1;
+            }
+        };
+        let point = match position {
+            Some(at) if matches.opt_present("pixel-at") => {
+                match util::parse_relative_point(&at, screen) {
+                    Some(p) => Some(p),
+                    None => {
+                        eprintln!("Invalid pixel position, expected X,Y");
+                        return 1;
+                    }
+                }
+            }
+            Some(g) => match util::parse_relative_geometry(&g, screen) {
+                Some(r) => Some(util::Point { x: r.x, y: r.y }),
+                None => {
+                    eprintln!("Invalid geometry");
+                    return 1;
+                }
+            },
+            None => None,
+        };
+
+        wait_delay(delay, matches.opt_present("d"));
+
+        let point = match point.or_else(|| display.get_cursor_position()) {
+            Some(p) => p,
+            None => {
+                eprintln!("Failed to get cursor position");
+                return 1;
+            }
+        };
+        let rect = util::Rect {
+            x: point.x,
+            y: point.y,
+            w: 1,
+            h: 1,
+        };
+        let pixel = match grab_image(&display, root, rect, &mut timings) {
+            Some(i) => *i.get_pixel(0, 0),
+            None => return 1,
+        };
+
+        let [r, g, b, a] = pixel.0;
+        match pixel_format {
+            "hex" if a == 255 => println!("#{r:02x}{g:02x}{b:02x}"),
+            "hex" => println!("#{r:02x}{g:02x}{b:02x}{a:02x}"),
+            "rgb" => println!("{r} {g} {b}"),
+            _ => println!("{r} {g} {b} {a}"),
+        }
+        return 0;
+    }
+
+    if matches.opt_present("dry-run-list-screens") {
+        let screens = match display.get_screens() {
+            Some(s) => s,
+            None => {
+                eprintln!("Failed to get screens");
+                return 1;
+            }
+        };
+        let cursor = display.get_cursor_position();
+
+        println!("INDEX  NAME        GEOMETRY              ROTATION  CURSOR");
+        for (i, screen) in screens.iter().enumerate() {
+            let has_cursor = cursor.is_some_and(|c| screen.rect.contains(c));
+            println!(
+                "{:<6} {:<11} {:<21} {:<9} {}",
+                i,
+                screen.name.as_deref().unwrap_or("-"),
+                screen.rect.to_string(),
+                screen.rotation,
+                if has_cursor { "*" } else { "" },
+            );
+        }
+        return 0;
+    }
+
+    if matches.opt_present("list-windows") {
+        let tree = match display.get_window_tree(matches.opt_present("all")) {
+            Some(t) => t,
+            None => {
+                eprintln!("Failed to list windows");
+                return 1;
+            }
+        };
+
+        for (depth, window) in tree {
+            let geometry = display
+                .get_window_geometry(window)
+                .map_or_else(|| "-".to_string(), |r| r.to_string());
+            let name = display
+                .get_window_name(window)
+                .map_or_else(|| "-".to_string(), |n| format!("{n:?}"));
+            let class = display
+                .get_wm_class(window)
+                .map_or_else(|| "-".to_string(), |(i, c)| format!("{i}.{c}"));
+            println!(
+                "{:indent$}{window:#x}  {geometry}  {name}  {class}",
+                "",
+                indent = (depth - 1) * 2
+            );
+        }
+        return 0;
+    } else if matches.opt_present("all") {
+        eprintln!("--all can only be used with --list-windows");
+        return 1;
+    }
+
+    if matches.opt_present("capture-pointer-shape-name") {
+        match display.get_cursor_name() {
+            Some(name) => {
+                println!("{name}");
+                return 0;
+            }
+            None => {
+                eprintln!(
+                    "Failed to get cursor name, the cursor may be unnamed or XFixes unavailable"
+                );
+                return 1;
+            }
+        }
+    }
+
+    let window_id = match matches.opt_str("i") {
+        Some(s) => match util::parse_int::<xproto::Window>(&s) {
+            Ok(r) => Some(r),
+            Err(_) => {
+                eprintln!("Window ID is not a valid integer");
+                eprintln!("Accepted values are decimal, hex (0x*), octal (0o*) and binary (0b*)");
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let region = match matches.opt_str("capture-region-from-window") {
+        Some(s) => match util::parse_window_region(&s) {
+            Some(r) => Some(r),
+            None => {
+                eprintln!("Invalid window region, expected ID:WxH+X+Y");
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let stack = match matches.opt_str("window-stack") {
+        Some(s) => {
+            let ids: Result<Vec<xproto::Window>, _> = s
+                .split(',')
+                .map(util::parse_int::<xproto::Window>)
+                .collect();
+            match ids {
+                Ok(ids) => Some(ids),
+                Err(_) => {
+                    eprintln!("Window stack is not a comma-separated list of valid integers");
+                    return 1;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let span = match matches.opt_str("span") {
+        Some(s) => {
+            let ids: Result<Vec<xproto::Window>, _> = s
+                .split(',')
+                .map(util::parse_int::<xproto::Window>)
+                .collect();
+            match ids {
+                Ok(ids) if ids.len() == 2 => Some(ids),
+                _ => {
+                    eprintln!("--span takes two comma-separated window IDs");
+                    return 1;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let mut censored = Vec::new();
+    for spec in matches.opt_strs("censor") {
+        let parsed = spec.split_once(':').and_then(|(geometry, method)| {
+            Some((
+                util::parse_geometry(geometry)?,
+                effects::Censor::parse(method)?,
+            ))
+        });
+        match parsed {
+            Some(c) => censored.push(c),
+            None => {
+                eprintln!(
+                    "Invalid censored area {spec}, expected WxH+X+Y:METHOD with METHOD one of {}",
+                    effects::Censor::NAMES.join(", ")
+                );
+                return 1;
+            }
+        }
+    }
+
+    let stitch = match matches.opt_strs("g").len() {
+        0 | 1 => {
+            for opt in ["stack", "gutter"] {
+                if matches.opt_present(opt) {
+                    eprintln!("{} requires several -g", flag_name(opt));
+                    return 1;
+                }
+            }
+            None
+        }
+        _ => {
+            // Stitched areas are always taken from the whole screen
+            let screen = match display.get_window_geometry(root) {
+                Some(r) => r,
+                None => {
+                    eprintln!("Failed to get window geometry");
+                    return 1;
+                }
+            };
+            let mut rects = Vec::new();
+            for g in matches.opt_strs("g") {
+                match util::parse_relative_geometry(&g, screen) {
+                    Some(r) => rects.push(r),
+                    None => {
+                        eprintln!("Invalid geometry {g}");
+                        return 1;
+                    }
+                }
+            }
+            let layout = match matches.opt_str("stack") {
+                Some(name) => match effects::Layout::from_name(&name) {
+                    Some(l) => l,
+                    None => {
+                        eprintln!(
+                            "Invalid layout, expected one of {}",
+                            effects::Layout::NAMES.join(", ")
+                        );
+                        return 1;
+                    }
+                },
+                None => effects::Layout::Horizontal,
+            };
+            let gutter = match matches.opt_str("gutter").map(|g| g.parse::<u32>()) {
+                Some(Ok(g @ 0..=MAX_GUTTER)) => g,
+                Some(_) => {
+                    eprintln!("Gutter must be between 0 and {MAX_GUTTER} pixels");
+                    return 1;
+                }
+                None => 0,
+            };
+            Some((rects, layout, gutter))
+        }
+    };
+
+    let contact_sheet = match (
+        matches.opt_present("contact-sheet"),
+        matches.opt_str("class"),
+    ) {
+        (true, Some(class)) => Some(class),
+        (true, None) => {
+            eprintln!("--contact-sheet requires --class");
+            return 1;
+        }
+        (false, _) => {
+            for opt in ["class", "columns", "spacing", "label"] {
+                if matches.opt_present(opt) {
+                    eprintln!("{} requires --contact-sheet", flag_name(opt));
+                    return 1;
+                }
+            }
+            None
+        }
+    };
+    let columns = match matches.opt_str("columns") {
+        Some(n) => match n.parse() {
+            Ok(n @ 1..=MAX_COLUMNS) => Some(n),
+            _ => {
+                eprintln!("Number of columns must be an integer between 1 and {MAX_COLUMNS}");
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let spacing = match matches.opt_str("spacing") {
+        Some(px) => match px.parse() {
+            Ok(px @ 0..=MAX_SPACING) => px,
+            _ => {
+                eprintln!("Spacing must be an integer between 0 and {MAX_SPACING}");
+                return 1;
+            }
+        },
+        None => 8,
+    };
+
+    let filename_template = matches.opt_str("filename");
+    if filename_template.is_some() && (!matches.free.is_empty() || base64) {
+        eprintln!("--filename names the output file, cannot use it with another output");
+        return 1;
+    }
+
+    // The configured file name only applies when nothing else names or takes the output
+    let template = filename_template
+        .as_ref()
+        .or(config.filename.as_ref().filter(|_| !base64 && !clipboard));
+    let (output_format, quality) = match output_format(&matches, &config, template) {
+        Some(f) => f,
+        None => return 1,
+    };
+
+    let mut also = Vec::new();
+    for spec in matches.opt_strs("also") {
+        let (name, file) = match spec.split_once(':') {
+            Some((name, file)) if !file.is_empty() => (name, file.to_string()),
+            _ => {
+                eprintln!("Invalid --also {spec}, expected FORMAT:FILE");
+                return 1;
+            }
+        };
+        match format::lookup(name) {
+            Some(f) if f.available => also.push((f.format, file)),
+            Some(f) => {
+                eprintln!("Support for {} was not compiled into this build", f.name);
+                return 1;
+            }
+            None => {
+                eprintln!("Invalid image format {name} in --also {spec}");
+                return 1;
+            }
+        }
+    }
+
+    let wait_nonblack = if matches.opt_present("wait-nonblack") {
+        let secs = matches
+            .opt_str("wait-timeout")
+            .unwrap_or_else(|| "5".to_string());
+        match secs
+            .parse::<f64>()
+            .ok()
+            .filter(|s| s.is_finite() && *s >= 0.0)
+        {
+            Some(secs) => Some(time::Duration::from_secs_f64(secs)),
+            None => {
+                eprintln!("Wait timeout must be a non-negative number of seconds");
+                return 1;
+            }
+        }
+    } else {
+        if matches.opt_present("wait-timeout") {
+            eprintln!("--wait-timeout can only be used with --wait-nonblack");
+            return 1;
+        }
+        None
+    };
+
+    let limit_bytes = match matches.opt_str("limit-bytes") {
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) if n > 0 => Some(n),
+            _ => {
+                eprintln!("--limit-bytes must be a positive integer");
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let lossy_fallback = if matches.opt_present("allow-lossy-fallback") {
+        if limit_bytes.is_none() {
+            eprintln!("--allow-lossy-fallback requires --limit-bytes");
+            return 1;
+        }
+        // The fallback changes the format, which a file name picked by the user would contradict
+        let named_output = output.is_some_and(|p| p != "-")
+            || (output.is_none()
+                && !base64
+                && (filename_template.is_some() || (!clipboard && config.filename.is_some())));
+        if named_output {
+            eprintln!(
+                "--allow-lossy-fallback can only be used with the default output name or stdout"
+            );
+            return 1;
+        }
+        // Other formats can't hold the pages of the existing file
+        if matches.opt_present("append") {
+            eprintln!("Cannot use --allow-lossy-fallback with --append");
+            return 1;
+        }
+        match format::FORMATS
+            .iter()
+            .find(|f| f.has_quality && f.available)
+        {
+            Some(f) => Some(f.format),
+            None => {
+                eprintln!("No lossy format was compiled into this build, cannot fall back to one");
+                return 1;
+            }
+        }
+    } else {
+        None
+    };
+
+    let max_time = match matches.opt_str("max-time") {
+        Some(secs) => match secs
+            .parse::<f64>()
+            .ok()
+            .filter(|s| s.is_finite() && *s > 0.0)
+        {
+            Some(secs) => Some(time::Duration::from_secs_f64(secs)),
+            None => {
+                eprintln!("Maximum time must be a positive number of seconds");
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let jitter = match matches.opt_str("capture-delay-jitter") {
+        Some(ms) => match ms.parse() {
+            Ok(ms) => Some(time::Duration::from_millis(ms)),
+            Err(_) => {
+                eprintln!("Jitter delay must be a non-negative number of milliseconds");
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let pointer_scale = match matches.opt_str("pointer-scale") {
+        Some(n) => match n.parse::<u32>() {
+            Ok(n @ 1..=MAX_POINTER_SCALE) => Some(n),
+            _ => {
+                eprintln!("--pointer-scale must be an integer between 1 and {MAX_POINTER_SCALE}");
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let cursor_size = match matches.opt_str("cursor-size") {
+        Some(n) => match n.parse::<u32>() {
+            Ok(n @ 1..=MAX_CURSOR_SIZE) => Some(n),
+            _ => {
+                eprintln!("--cursor-size must be an integer between 1 and {MAX_CURSOR_SIZE}");
+                return 1;
+            }
+        },
+        None => None,
+    };
+    for opt in ["pointer-scale", "cursor-size"] {
+        if matches.opt_present(opt)
+            && (matches.opt_present("no-cursor")
+                || !(matches.opt_present("cursor") || config.cursor == Some(true)))
+        {
+            eprintln!("{} requires -p", flag_name(opt));
+            return 1;
+        }
+    }
+
+    let grid = match matches.opt_str("grid") {
+        Some(g) => {
+            let parsed = g.split_once(':').and_then(|(spacing, color)| {
+                Some((
+                    spacing.parse::<u32>().ok().filter(|&s| s > 0)?,
+                    util::parse_color(color)?,
+                ))
+            });
+            match parsed {
+                Some(g) => Some(g),
+                None => {
+                    eprintln!("Invalid grid, expected SPACING:COLOR, e.g. 10:#ff000080");
+                    return 1;
+                }
+            }
+        }
+        None => None,
+    };
+    let grid_major = match matches.opt_str("grid-major") {
+        Some(n) => match n.parse() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                eprintln!("--grid-major must be a positive integer");
+                return 1;
+            }
+        },
+        None => 10,
+    };
+    let grid_origin = match matches.opt_str("grid-origin") {
+        Some(o) => {
+            let parsed = o
+                .split_once(',')
+                .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)));
+            match parsed {
+                Some((x, y)) => util::Point { x, y },
+                None => {
+                    eprintln!("Invalid grid origin, expected X,Y");
+                    return 1;
+                }
+            }
+        }
+        None => util::Point { x: 0, y: 0 },
+    };
+    if grid.is_none() && (matches.opt_present("grid-major") || matches.opt_present("grid-origin")) {
+        eprintln!("--grid-major and --grid-origin require --grid");
+        return 1;
+    }
+
+    let watermark = match matches.opt_str("watermark") {
+        Some(w) => {
+            // File names may contain colons, the other fields can't
+            let mut fields = w.rsplitn(3, ':');
+            let parsed = match (fields.next(), fields.next(), fields.next()) {
+                (Some(opacity), Some(position), Some(file)) => effects::Position::parse(position)
+                    .zip(opacity.parse::<f32>().ok().filter(|o| o.is_finite()))
+                    .map(|(position, opacity)| (file, position, opacity)),
+                _ => None,
+            };
+            let (file, position, opacity) = match parsed {
+                Some(p) => p,
+                None => {
+                    eprintln!(
+                        "Invalid watermark, expected FILE:POSITION:OPACITY, \
+                            where POSITION is X,Y or one of {}",
+                        effects::Position::NAMES.join(", ")
+                    );
+                    return 1;
+                }
+            };
+            match image::open(file) {
+                Ok(i) => Some((i.to_rgba8(), position, opacity)),
+                Err(e) => {
+                    eprintln!("Failed to load watermark {}: {}", file, e);
+                    return 1;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let baseline = match matches.opt_str("diff") {
+        Some(file) => match image::open(&file) {
+            Ok(i) => Some(i.to_rgba8()),
+            Err(e) => {
+                eprintln!("Failed to load baseline {}: {}", file, e);
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    // None means following the rotation of the screen
+    let rotation = match matches.opt_str("rotate").as_deref() {
+        Some("auto") => None,
+        Some(d @ ("90" | "180" | "270")) => Some(d.parse().expect("Invalid rotation")),
+        Some(_) => {
+            eprintln!("Rotation must be 90, 180, 270 or auto");
+            return 1;
+        }
+        None => Some(0),
+    };
+    let flip = match matches.opt_str("flip").as_deref() {
+        Some("h") => Some(effects::Flip::Horizontal),
+        Some("v") => Some(effects::Flip::Vertical),
+        Some(_) => {
+            eprintln!("Flip must be h (horizontal) or v (vertical)");
+            return 1;
+        }
+        None => None,
+    };
+
+    let fit = match matches.opt_str("fit") {
+        Some(s) => match util::parse_size(&s) {
+            Some((w, h)) if w > MAX_IMAGE_SIZE || h > MAX_IMAGE_SIZE => {
+                eprintln!("--fit can't be larger than {MAX_IMAGE_SIZE}x{MAX_IMAGE_SIZE}");
+                return 1;
+            }
+            Some(size) => Some(size),
+            None => {
+                eprintln!("Invalid size for --fit, expected WxH with positive dimensions");
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let scale = match matches.opt_str("scale") {
+        Some(s) => match util::parse_percentage(&s) {
+            Some(factor) => Some(factor),
+            None => {
+                eprintln!("Invalid scale, expected a positive percentage such as 50%");
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let resize = match matches.opt_str("resize") {
+        Some(s) => match util::parse_resize(&s) {
+            Some((w, h)) if w > MAX_IMAGE_SIZE || h > MAX_IMAGE_SIZE => {
+                eprintln!("--resize can't be larger than {MAX_IMAGE_SIZE}x{MAX_IMAGE_SIZE}");
+                return 1;
+            }
+            Some(size) => Some(size),
+            None => {
+                eprintln!("Invalid size for --resize, expected WxH, Wx0 or 0xH");
+                return 1;
+            }
+        },
+        None => None,
+    };
+    if matches.opt_present("linear-downscale") && scale.is_none() && resize.is_none() {
+        eprintln!("--linear-downscale requires --scale or --resize");
+        return 1;
+    }
+    if [scale.is_some(), resize.is_some(), fit.is_some()]
+        .iter()
+        .filter(|&&s| s)
+        .count()
+        > 1
+    {
+        eprintln!("Only one of --scale, --resize and --fit can be used");
+        return 1;
+    }
+    let scale_filter = match matches.opt_str("scale-filter").as_deref() {
+        None | Some("lanczos3") => imageops::FilterType::Lanczos3,
+        Some("triangle") => imageops::FilterType::Triangle,
+        Some("nearest") => imageops::FilterType::Nearest,
+        Some(name) => {
+            eprintln!(
+                "Unknown scaling filter {name}, expected one of: nearest, triangle, lanczos3"
+            );
+            return 1;
+        }
+    };
+    if scale.is_none() && resize.is_none() && matches.opt_present("scale-filter") {
+        eprintln!("--scale-filter requires --scale or --resize");
+        return 1;
+    }
+
+    let dither = match matches.opt_str("dither") {
+        Some(bits) => match bits.parse() {
+            Ok(bits @ 1..=8) => Some(bits),
+            _ => {
+                eprintln!("--dither takes a number of bits between 1 and 8");
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let dither_method = match matches.opt_str("dither-method") {
+        Some(name) => match effects::Dither::from_name(&name) {
+            Some(m) => m,
+            None => {
+                eprintln!(
+                    "Unknown dithering method {name}, expected one of: {}",
+                    effects::Dither::NAMES.join(", ")
+                );
+                return 1;
+            }
+        },
+        None => effects::Dither::FloydSteinberg,
+    };
+    if dither.is_none() && matches.opt_present("dither-method") {
+        eprintln!("--dither-method requires --dither");
+        return 1;
+    }
+
+    let mut encode_options = format::EncodeOptions::default();
+    match parse_quality(quality, output_format) {
+        Some(q) => encode_options.quality = q,
+        None => return 1,
+    }
+    if let Some(speed) = matches.opt_str("avif-speed") {
+        if output_format != Format::Avif {
+            eprintln!("--avif-speed can only be used with AVIF output");
+            return 1;
+        }
+        match speed.parse() {
+            Ok(speed @ 0..=10) => encode_options.avif_speed = Some(speed),
+            _ => {
+                eprintln!("AVIF speed must be an integer between 0 and 10");
+                return 1;
+            }
+        }
+    }
+
+    if let Some(color) = matches.opt_str("background") {
+        match util::parse_color(&color) {
+            Some(c) => encode_options.background = Some(c),
+            None => {
+                eprintln!("Invalid background color, expected RRGGBB in hex or a color name");
+                eprintln!(
+                    "Color names: {}",
+                    util::COLOR_NAMES
+                        .iter()
+                        .map(|(n, _)| *n)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                return 1;
+            }
+        }
+    }
+    encode_options.no_alpha = matches.opt_present("no-alpha");
+
+    // Only known once the capture area is
+    let mut dpi_auto = false;
+    if let Some(dpi) = matches.opt_str("dpi") {
+        if matches.opt_present("strip-metadata") {
+            eprintln!("Cannot use --dpi with --strip-metadata");
+            return 1;
+        }
+        if !matches!(output_format, Format::Png | Format::Jpeg) {
+            eprintln!("--dpi can only be used with PNG and JPEG output");
+            return 1;
+        }
+        match dpi.parse() {
+            Ok(dpi) if dpi > 0 => encode_options.dpi = Some(dpi),
+            _ if dpi == "auto" => dpi_auto = true,
+            _ => {
+                eprintln!("DPI must be auto or an integer between 1 and 65535");
+                return 1;
+            }
+        }
+    }
+
+    if let Some(level) = matches.opt_str("png-level") {
+        if !matches!(output_format, Format::Png | Format::Svg) {
+            eprintln!("--png-level can only be used with PNG output");
+            return 1;
+        }
+        match level.parse() {
+            Ok(level @ 0..=9) => encode_options.png_level = Some(level),
+            _ => {
+                eprintln!("PNG compression level must be an integer between 0 and 9");
+                return 1;
+            }
+        }
+    }
+    if let Some(preset) = matches.opt_str("png-compression") {
+        if !matches!(output_format, Format::Png | Format::Svg) {
+            eprintln!("--png-compression can only be used with PNG output");
+            return 1;
+        }
+        if matches.opt_present("png-level") {
+            eprintln!("Cannot use --png-compression and --png-level together");
+            return 1;
+        }
+        match format::PngCompression::parse(&preset) {
+            Some(p) => encode_options.png_compression = Some(p),
+            None => {
+                eprintln!(
+                    "Invalid PNG compression, expected one of {}",
+                    format::PngCompression::NAMES.join(", ")
+                );
+                return 1;
+            }
+        }
+    }
+    if matches.opt_present("raw-header") {
+        if output_format != Format::Raw {
+            eprintln!("--raw-header can only be used with raw output");
+            return 1;
+        }
+        encode_options.raw_header = true;
+    }
+    if let Some(tupltype) = matches.opt_str("pam-tupltype") {
+        if output_format != Format::Pam {
+            eprintln!("--pam-tupltype can only be used with PAM output");
+            return 1;
+        }
+        encode_options.pam_rgb = match tupltype.to_uppercase().as_str() {
+            "RGB" => true,
+            "RGB_ALPHA" => false,
+            _ => {
+                eprintln!("PAM tuple type must be RGB or RGB_ALPHA");
+                return 1;
+            }
+        };
+    }
+
+    let embed_geometry = matches.opt_present("embed-geometry");
+    if embed_geometry && matches.opt_present("strip-metadata") {
+        eprintln!("Cannot use --embed-geometry with --strip-metadata");
+        return 1;
+    }
+    if embed_geometry && output_format != Format::Png {
+        eprintln!("--embed-geometry is only supported for PNG output");
+        return 1;
+    }
+    let metadata = matches.opt_present("metadata");
+    if metadata && matches.opt_present("strip-metadata") {
+        eprintln!("Cannot use --metadata with --strip-metadata");
+        return 1;
+    }
+    if metadata && output_format != Format::Png {
+        eprintln!("--metadata is only supported for PNG output");
+        return 1;
+    }
+
+    if matches.opt_present("s") {
+        if matches.opt_present("g") {
+            eprintln!("Cannot use -g and -s at the same time");
+            return 1;
+        }
+        if matches.opt_present("i") {
+            eprintln!("Cannot use -i and -s at the same time");
+            return 1;
+        }
+    }
+
+    if matches.opt_present("name") {
+        for opt in [
+            "i",
+            "s",
+            "active",
+            "interactive",
+            "region",
+            "window-under-cursor",
+            "xinerama-screen",
+            "monitor",
+            "primary",
+            "window-stack",
+            "isolate-window",
+            "work-area",
+            "span",
+            "contact-sheet",
+            "capture-region-from-window",
+            "wallpaper-only",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --name with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    } else if matches.opt_present("name-exact") || matches.opt_present("all-matches") {
+        eprintln!("--name-exact and --all-matches require --name");
+        return 1;
+    }
+
+    if matches.opt_present("active") {
+        for opt in [
+            "i",
+            "s",
+            "interactive",
+            "region",
+            "window-under-cursor",
+            "xinerama-screen",
+            "monitor",
+            "primary",
+            "window-stack",
+            "isolate-window",
+            "work-area",
+            "span",
+            "contact-sheet",
+            "capture-region-from-window",
+            "wallpaper-only",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --active with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("region") {
+        for opt in [
+            "i",
+            "g",
+            "s",
+            "interactive",
+            "window-under-cursor",
+            "xinerama-screen",
+            "monitor",
+            "primary",
+            "window-stack",
+            "span",
+            "contact-sheet",
+            "capture-region-from-window",
+            "with-transients",
+            "decorations",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --region with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("freeze") {
+        if !matches.opt_present("region") {
+            eprintln!("--freeze requires -r");
+            return 1;
+        }
+        for opt in [
+            "wallpaper-only",
+            "wait-nonblack",
+            "capture-delay-jitter",
+            "fbdev-fallback",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --freeze with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("deepest") && !matches.opt_present("interactive") {
+        eprintln!("--deepest requires -k");
+        return 1;
+    }
+
+    if matches.opt_present("interactive") {
+        for opt in [
+            "i",
+            "s",
+            "window-under-cursor",
+            "xinerama-screen",
+            "monitor",
+            "primary",
+            "window-stack",
+            "isolate-window",
+            "work-area",
+            "span",
+            "contact-sheet",
+            "capture-region-from-window",
+            "wallpaper-only",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --interactive with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("window-under-cursor") {
+        for opt in [
+            "i",
+            "s",
+            "xinerama-screen",
+            "monitor",
+            "primary",
+            "window-stack",
+            "isolate-window",
+            "work-area",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --window-under-cursor with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("xinerama-screen") {
+        for opt in ["i", "g", "s", "monitor", "primary"] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --xinerama-screen with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("monitor") {
+        for opt in ["i", "g", "s", "primary"] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --monitor with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("primary") {
+        for opt in ["i", "g", "s"] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --primary with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if stack.is_some() {
+        for opt in [
+            "i",
+            "g",
+            "s",
+            "xinerama-screen",
+            "monitor",
+            "primary",
+            "work-area",
+            "isolate-window",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --window-stack with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if span.is_some() {
+        for opt in [
+            "i",
+            "g",
+            "s",
+            "window-under-cursor",
+            "xinerama-screen",
+            "monitor",
+            "primary",
+            "window-stack",
+            "contact-sheet",
+            "capture-region-from-window",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --span with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("wallpaper-only") {
+        for opt in [
+            "i",
+            "window-under-cursor",
+            "window-stack",
+            "contact-sheet",
+            "capture-region-from-window",
+            "span",
+            "isolate-window",
+            "render",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --wallpaper-only with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    let with_transients = matches.opt_present("with-transients");
+    if with_transients {
+        if !["i", "window-under-cursor", "interactive", "active", "name"]
+            .iter()
+            .any(|&opt| matches.opt_present(opt))
+        {
+            eprintln!(
+                "--with-transients requires a window (-i, -a, -k, --name or --window-under-cursor)"
+            );
+            return 1;
+        }
+        for opt in ["g", "window-stack", "work-area", "isolate-window"] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --with-transients with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("decorations") {
+        if !["i", "window-under-cursor", "interactive", "active", "name"]
+            .iter()
+            .any(|&opt| matches.opt_present(opt))
+        {
+            eprintln!(
+                "--decorations requires a window (-i, -a, -k, --name or --window-under-cursor)"
+            );
+            return 1;
+        }
+        for opt in [
+            "g",
+            "window-stack",
+            "work-area",
+            "isolate-window",
+            "capture-region-from-window",
+            "with-transients",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --decorations with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if contact_sheet.is_some() {
+        for opt in [
+            "i",
+            "g",
+            "s",
+            "window-under-cursor",
+            "xinerama-screen",
+            "monitor",
+            "primary",
+            "window-stack",
+            "isolate-window",
+            "work-area",
+            "capture-region-from-window",
+            "embed-geometry",
+            "cursor",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use --contact-sheet with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if stitch.is_some() {
+        for opt in [
+            "i",
+            "s",
+            "active",
+            "interactive",
+            "region",
+            "name",
+            "window-under-cursor",
+            "xinerama-screen",
+            "monitor",
+            "primary",
+            "window-stack",
+            "span",
+            "contact-sheet",
+            "capture-region-from-window",
+            "wallpaper-only",
+            "isolate-window",
+            "work-area",
+            "embed-geometry",
+            "cursor",
+            "wait-nonblack",
+            "capture-delay-jitter",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!("Cannot use several -g with {}", flag_name(opt));
+                return 1;
+            }
+        }
+    }
+
+    if region.is_some() {
+        for opt in [
+            "i",
+            "g",
+            "s",
+            "window-under-cursor",
+            "xinerama-screen",
+            "monitor",
+            "primary",
+            "window-stack",
+            "work-area",
+        ] {
+            if matches.opt_present(opt) {
+                eprintln!(
+                    "Cannot use --capture-region-from-window with {}",
+                    flag_name(opt)
+                );
+                return 1;
+            }
+        }
+    }
+
+    let isolate = match matches.opt_str("isolate-window") {
+        Some(s) => match util::parse_int::<xproto::Window>(&s) {
+            Ok(id) => Some(id),
+            Err(_) => {
+                eprintln!("Isolated window ID is not a valid integer");
+                return 1;
+            }
+        },
+        None => None,
+    };
+    if isolate.is_some() && matches.opt_present("i") {
+        eprintln!("Cannot use -i and --isolate-window at the same time");
+        return 1;
+    }
+
+    if matches.opt_present("work-area") && matches.opt_present("i") {
+        eprintln!("Cannot use -i and --work-area at the same time");
+        return 1;
+    }
+
+    // Anything that depends on the state of the screen (which window is hovered, where the cursor
+    // is) is resolved after the delay, so that it can be set up during the wait
+    wait_delay(delay, matches.opt_present("d"));
+
+    let capture_start = time::Instant::now();
+    let out_of_time = || max_time.is_some_and(|t| capture_start.elapsed() >= t);
+    // Waits before capturing again are cut short by --max-time
+    let sleep_within_time = |d: time::Duration| {
+        thread::sleep(max_time.map_or(d, |t| d.min(t.saturating_sub(capture_start.elapsed()))))
+    };
+
+    let named = match matches.opt_str("name") {
+        Some(name) => {
+            let exact = matches.opt_present("name-exact");
+            let found = match display.find_windows_by_name(&name, exact) {
+                Some(f) => f,
+                None => {
+                    eprintln!("Failed to list windows");
+                    return 1;
+                }
+            };
+            match found[..] {
+                [] => {
+                    eprintln!("No window matches {name}");
+                    return 1;
+                }
+                [_, _, ..] if matches.opt_present("all-matches") => {
+                    eprintln!("Several windows match {name}:");
+                    for w in found {
+                        let title = display.get_window_name(w).unwrap_or_default();
+                        eprintln!("{w:#x}\t{title}");
+                    }
+                    return 1;
+                }
+                [first, ..] => Some(first),
+            }
+        }
+        None => None,
+    };
+
+    let window = match window_id.or(region.map(|(id, _)| id)).or(named) {
+        Some(id) => id,
+        None if matches.opt_present("window-under-cursor") => {
+            match display.get_window_under_cursor() {
+                Some(w) => w,
+                None => {
+                    eprintln!("There is no window under the cursor");
+                    return 1;
+                }
+            }
+        }
+        None if matches.opt_present("active") => match display.get_active_window() {
+            Some(w) => w,
+            None => {
+                eprintln!("No window has the focus");
+                return 1;
+            }
+        },
+        None if matches.opt_present("interactive") => {
+            match display.select_window_by_click(matches.opt_present("deepest")) {
+                Some(w) => w,
+                None => {
+                    eprintln!("No window was selected");
+                    return 1;
+                }
+            }
+        }
+        None => root,
+    };
+
+    // Reparenting window managers draw decorations in a frame window around the client, which
+    // can be captured instead. Others only report their size, so capture that area of the screen
+    let mut decorated = None;
+    let window = if matches.opt_present("decorations") {
+        match display.get_frame_window(window) {
+            Some(frame) if frame != window => frame,
+            _ => match display.get_frame_extents(window) {
+                Some([left, right, top, bottom]) if left + right + top + bottom > 0 => {
+                    let r = match display.get_window_geometry(window) {
+                        Some(r) => r,
+                        None => {
+                            eprintln!("Failed to get window geometry");
+                            return 1;
+                        }
+                    };
+                    decorated = Some(util::Rect {
+                        x: r.x - left as i32,
+                        y: r.y - top as i32,
+                        w: r.w + (left + right) as i32,
+                        h: r.h + (top + bottom) as i32,
+                    });
+                    root
+                }
+                _ => window,
+            },
+        }
+    } else {
+        window
+    };
+
+    // The wallpaper pixmap shares the coordinates of the root window, so everything else can
+    // carry on as if capturing the root window
+    let drawable = if matches.opt_present("wallpaper-only") {
+        match display.get_wallpaper_pixmap() {
+            Some(p) => p,
+            None => {
+                eprintln!("No wallpaper found, _XROOTPMAP_ID is not set on the root window");
+                eprintln!("It is set by wallpaper tools such as feh, nitrogen or xwallpaper");
+                return 1;
+            }
+        }
+    } else {
+        window
     };
 
-    let output_ext = matches
-        .opt_str("f")
-        .unwrap_or_else(|| "png".to_string())
-        .to_lowercase();
-    let output_format = match output_ext.as_ref() {
-        "png" => ImageOutputFormat::Png,
-        "pam" => ImageOutputFormat::Pnm(codecs::pnm::PnmSubtype::ArbitraryMap),
-        _ => {
-            eprintln!("Invalid image format specified");
-            return 1;
+    // Dialogs are separate top-level windows, draw them over their parent like --window-stack
+    let stack = if with_transients {
+        match find_transients(&display, window) {
+            Some(transients) => Some(std::iter::once(window).chain(transients).collect()),
+            None => {
+                eprintln!("Failed to list windows");
+                return 1;
+            }
         }
+    } else {
+        stack
     };
 
     let window_rect = match display.get_window_geometry(window) {
@@ -113,19 +2767,57 @@ fn run() -> i32 {
         }
     };
 
-    if matches.opt_present("s") {
-        if matches.opt_present("g") {
-            eprintln!("Cannot use -g and -s at the same time");
-            return 1;
+    // The capture happens before selecting, and only gets cropped afterwards
+    let mut frozen = None;
+    let geometry = if matches.opt_present("region") {
+        let _freeze = if matches.opt_present("freeze") {
+            let image = match grab_image(&display, root, window_rect, &mut timings) {
+                Some(i) => i,
+                None => return 1,
+            };
+            match display.freeze(&image) {
+                Some(f) => {
+                    frozen = Some(image);
+                    Some(f)
+                }
+                None => {
+                    eprintln!("Failed to freeze the screen, its pixel format is not supported");
+                    return 1;
+                }
+            }
+        } else {
+            None
+        };
+        match display.select_region() {
+            Some(r) => Some(r),
+            None => {
+                eprintln!("No region was selected");
+                return 1;
+            }
         }
-        if matches.opt_present("i") {
-            eprintln!("Cannot use -i and -s at the same time");
+    } else {
+        match matches
+            .opt_str("g")
+            .map(|s| util::parse_relative_geometry(&s, window_rect))
+        {
+            Some(Some(g)) => Some(g),
+            Some(None) => {
+                eprintln!("Invalid geometry");
+                return 1;
+            }
+            None => decorated,
+        }
+    };
+    // Areas given with -g must fit, others are clamped to what can be captured
+    let allow_partial = matches.opt_present("allow-partial");
+    let target = if window == root { "screen" } else { "window" };
+    if let Some(g) = geometry.filter(|_| matches.opt_present("g") && !allow_partial) {
+        if !geometry_fits(g, window_rect, target) {
             return 1;
         }
     }
-
-    let mut sel = match matches.opt_str("g") {
-        Some(s) => match util::parse_geometry(&s).and_then(|g| g.intersection(window_rect)) {
+    let mut sel = match geometry {
+        Some(g) => match g.intersection(window_rect) {
             Some(sel) => util::Rect {
                 // Selection is relative to the root window (whole screen)
                 x: sel.x - window_rect.x,
@@ -134,7 +2826,7 @@ fn run() -> i32 {
                 h: sel.h,
             },
             None => {
-                eprintln!("Invalid geometry");
+                eprintln!("Geometry {g} is outside the {target} ({window_rect})");
                 return 1;
             }
         },
@@ -146,6 +2838,75 @@ fn run() -> i32 {
         },
     };
 
+    // X can't capture the parts of a window that hang off the screen
+    if matches.opt_present("active") {
+        let root_rect = match display.get_window_geometry(root) {
+            Some(r) => r,
+            None => {
+                eprintln!("Failed to get window geometry");
+                return 1;
+            }
+        };
+        let on_screen = util::Rect {
+            x: sel.x + window_rect.x,
+            y: sel.y + window_rect.y,
+            ..sel
+        }
+        .intersection(root_rect);
+        sel = match on_screen {
+            Some(r) => util::Rect {
+                x: r.x - window_rect.x,
+                y: r.y - window_rect.y,
+                ..r
+            },
+            None => {
+                eprintln!("The active window is off-screen");
+                return 1;
+            }
+        };
+    }
+
+    if let Some((id, r)) = region {
+        let bounds = util::Rect {
+            x: 0,
+            y: 0,
+            ..window_rect
+        };
+        if !bounds.contains_rect(r) {
+            eprintln!(
+                "Region {r} does not fit within window {id:#x}, which is {}x{}",
+                window_rect.w, window_rect.h
+            );
+            return 1;
+        }
+        sel = r;
+    }
+
+    let span_rects = match span {
+        Some(ids) => {
+            let mut rects = Vec::new();
+            for id in ids {
+                match display.get_window_geometry(id) {
+                    Some(r) => rects.push(r),
+                    None => {
+                        eprintln!("Failed to get geometry of window {id:#x}");
+                        return 1;
+                    }
+                }
+            }
+
+            sel = match rects[0].union(rects[1]).intersection(window_rect) {
+                Some(r) => r,
+                None => {
+                    eprintln!("Both windows are off-screen");
+                    return 1;
+                }
+            };
+            Some(rects)
+        }
+        None => None,
+    };
+
     let screen_rects = match display.get_screen_rects() {
         Some(r) => r,
         None => {
@@ -173,54 +2934,518 @@ fn run() -> i32 {
         }
     }
 
-    let image = match display.get_image(window, sel) {
-        Some(i) => i,
-        None => {
-            eprintln!("Failed to get image from X");
-            return 1;
+    if let Some(n) = matches.opt_str("xinerama-screen") {
+        let rects = match display.get_xinerama_screen_rects() {
+            Some(r) if !r.is_empty() => r,
+            _ => {
+                eprintln!("Xinerama is not available");
+                return 1;
+            }
+        };
+
+        sel = match n.parse::<usize>().ok().and_then(|n| rects.get(n)) {
+            Some(r) => *r,
+            None => {
+                eprintln!(
+                    "Invalid Xinerama screen {n}, valid screens are 0 to {}",
+                    rects.len() - 1
+                );
+                return 1;
+            }
         }
-    };
+    }
 
-    let mut image = match image.to_image_buffer() {
-        Some(i) => i,
-        None => {
-            eprintln!(
-                "Failed to convert captured framebuffer, \
-                    only RGB565 and 8bpc formats are supported.\n\
-                    See https://github.com/neXromancers/shotgun/issues/35."
-            );
-            return 1;
+    if let Some(name) = matches.opt_str("monitor") {
+        let screens = match display.get_screens() {
+            Some(s) => s,
+            None => {
+                eprintln!("Failed to get screens");
+                return 1;
+            }
+        };
+
+        sel = match screens
+            .iter()
+            .find(|s| s.name.as_deref() == Some(name.as_str()))
+        {
+            Some(s) => s.rect,
+            None => {
+                let names: Vec<_> = screens.iter().filter_map(|s| s.name.as_deref()).collect();
+                if names.is_empty() {
+                    eprintln!("No output {name}, RandR doesn't name any screen");
+                } else {
+                    eprintln!("No output {name}, valid outputs are {}", names.join(", "));
+                }
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("primary") {
+        sel = match display.get_primary_screen_rect() {
+            Some(r) => r,
+            None => {
+                eprintln!(
+                    "No primary output is enabled, set one with xrandr --output NAME --primary"
+                );
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("work-area") {
+        let work_area = match display.get_work_area() {
+            Some(r) => r,
+            None => {
+                eprintln!("Failed to get work area, is an EWMH window manager running?");
+                return 1;
+            }
+        };
+
+        sel = match sel.intersection(work_area) {
+            Some(r) => r,
+            None => {
+                eprintln!("Selection lies outside of the work area");
+                return 1;
+            }
+        }
+    }
+
+    if matches.opt_present("wait-vblank")
+        && display.wait_for_vblank(window, VBLANK_TIMEOUT).is_none()
+    {
+        // Without Present, at least make sure that a full frame was scanned out since the
+        // request, on the slowest screen showing the selection
+        eprintln!("Present didn't report a vertical blank, waiting for one refresh period instead");
+        let screen_sel = util::Rect {
+            x: sel.x + window_rect.x,
+            y: sel.y + window_rect.y,
+            ..sel
+        };
+        let rate = display.get_screens().and_then(|screens| {
+            screens
+                .iter()
+                .filter(|s| s.rect.intersection(screen_sel).is_some())
+                .filter_map(|s| s.refresh_rate)
+                .reduce(f64::min)
+        });
+        let frame = rate
+            .filter(|&r| r >= 1.0)
+            .map(|r| time::Duration::from_secs_f64(1.0 / r))
+            .unwrap_or(VBLANK_FALLBACK_DELAY);
+        thread::sleep(frame);
+    }
+
+    let (capture_rect, mut image) = if let Some(stack) = &stack {
+        let mut layers = Vec::new();
+        for &id in stack {
+            match capture_window(&display, id, &mut timings) {
+                Some(l) => layers.push(l),
+                None => {
+                    eprintln!("Failed to capture window {id:#x}");
+                    return 1;
+                }
+            }
+        }
+
+        // Lay the windows out at their relative screen positions, later ones on top
+        let bounds = layers
+            .iter()
+            .map(|(r, _)| *r)
+            .reduce(|a, b| a.union(b))
+            .expect("Window stack is empty");
+        let mut canvas =
+            RgbaImage::from_pixel(bounds.w as u32, bounds.h as u32, Rgba([0, 0, 0, 0]));
+        for (rect, layer) in &layers {
+            util::composite_over(&mut canvas, layer, rect.x - bounds.x, rect.y - bounds.y);
+        }
+        (bounds, canvas)
+    } else if let Some((rects, layout, gutter)) = &stitch {
+        let mut images = Vec::new();
+        let mut bounds: Option<util::Rect> = None;
+        for &rect in rects {
+            if !allow_partial && !geometry_fits(rect, window_rect, "screen") {
+                return 1;
+            }
+            let sel = match rect.intersection(window_rect) {
+                Some(r) => r,
+                None => {
+                    eprintln!("Geometry {rect} is off-screen");
+                    return 1;
+                }
+            };
+            let mut image = match grab_image(&display, root, sel, &mut timings) {
+                Some(i) => i,
+                None => return 1,
+            };
+            if !screen_rects.iter().any(|s| s.contains_rect(sel)) {
+                image = util::mask_screens(&image, sel, &screen_rects);
+            }
+            images.push(image);
+            bounds = Some(bounds.map_or(sel, |b| b.union(sel)));
+        }
+
+        let background = encode_options.background.unwrap_or(Rgba([0, 0, 0, 0]));
+        let stitched = effects::stitch(&images, *layout, *gutter, background);
+        (bounds.expect("No geometry to stitch"), stitched)
+    } else if let Some(class) = &contact_sheet {
+        let windows = match display.get_client_windows() {
+            Some(w) => w,
+            None => {
+                eprintln!("Failed to list windows");
+                return 1;
+            }
+        };
+
+        let mut tiles = Vec::new();
+        let mut labels = Vec::new();
+        let mut bounds: Option<util::Rect> = None;
+        for id in windows {
+            let matches_class = display.get_wm_class(id).is_some_and(|(instance, c)| {
+                instance.eq_ignore_ascii_case(class) || c.eq_ignore_ascii_case(class)
+            });
+            if !matches_class {
+                continue;
+            }
+            match capture_window(&display, id, &mut timings) {
+                Some((rect, tile)) => {
+                    tiles.push(tile);
+                    labels.push(format!("{id:#x}"));
+                    bounds = Some(bounds.map_or(rect, |b| b.union(rect)));
+                }
+                // Typically a window on another desktop
+                None => eprintln!("Skipping window {id:#x}, it is not visible"),
+            }
+        }
+        let bounds = match bounds {
+            Some(b) => b,
+            None => {
+                eprintln!("No visible window has class {class}");
+                return 1;
+            }
+        };
+
+        let columns = columns
+            .unwrap_or_else(|| (tiles.len() as f64).sqrt().ceil() as u32)
+            .clamp(1, MAX_COLUMNS);
+        let labels = matches.opt_present("label").then_some(&labels[..]);
+        let sheet =
+            effects::contact_sheet(&tiles, labels, columns, spacing, CONTACT_SHEET_MAX_SIZE);
+        (bounds, sheet)
+    } else {
+        let captured = match &frozen {
+            Some(f) => Some(
+                imageops::crop_imm(f, sel.x as u32, sel.y as u32, sel.w as u32, sel.h as u32)
+                    .to_image(),
+            ),
+            None => grab_image(&display, drawable, sel, &mut timings),
+        };
+        let mut image = match captured {
+            Some(i) => i,
+            None if matches.opt_present("fbdev-fallback") => {
+                let rect = util::Rect {
+                    x: sel.x + window_rect.x,
+                    y: sel.y + window_rect.y,
+                    ..sel
+                };
+                match grab_fbdev(rect) {
+                    Some(i) => i,
+                    None => return 1,
+                }
+            }
+            None => return 1,
+        };
+
+        if let Some(timeout) = wait_nonblack {
+            let start = time::Instant::now();
+            while util::is_black(&image) {
+                if start.elapsed() >= timeout {
+                    eprintln!("Screen is still black after waiting, capturing anyway");
+                    break;
+                }
+                if out_of_time() {
+                    eprintln!("Capture took longer than --max-time, aborting");
+                    return EX_TIMEOUT;
+                }
+                sleep_within_time(NONBLACK_POLL_INTERVAL);
+                image = match grab_image(&display, drawable, sel, &mut timings) {
+                    Some(i) => i,
+                    None => return 1,
+                };
+            }
+        }
+
+        // Tearing shows up as a difference between two captures taken in quick succession,
+        // keep capturing until two consecutive frames agree
+        if let Some(gap) = jitter {
+            let mut stable = false;
+            for _ in 0..TEARING_RETRIES {
+                if out_of_time() {
+                    eprintln!("Capture took longer than --max-time, aborting");
+                    return EX_TIMEOUT;
+                }
+                sleep_within_time(gap);
+                let next = match grab_image(&display, drawable, sel, &mut timings) {
+                    Some(i) => i,
+                    None => return 1,
+                };
+                stable = util::differing_fraction(&image, &next) <= TEARING_THRESHOLD;
+                image = next;
+                if stable {
+                    break;
+                }
+            }
+            if !stable {
+                eprintln!("Captures kept changing, the screenshot may be torn");
+            }
         }
+
+        // sel is relative to the captured window
+        let rect = util::Rect {
+            x: sel.x + window_rect.x,
+            y: sel.y + window_rect.y,
+            ..sel
+        };
+        (rect, image)
     };
 
-    // When capturing the root window, attempt to mask the off-screen areas
-    if window == root {
-        let screens: Vec<util::Rect> = screen_rects
+    if embed_geometry || metadata {
+        encode_options.geometry = Some(capture_rect);
+    }
+    if dpi_auto {
+        // The monitor showing most of the capture
+        let screens = display.get_screens().unwrap_or_default();
+        let screen = screens.iter().max_by_key(|s| {
+            s.rect
+                .intersection(capture_rect)
+                .map_or(0, |r| r.w as i64 * r.h as i64)
+        });
+        encode_options.dpi = match screen.and_then(|s| util::dpi(s.rect.w, s.physical_size?.0)) {
+            Some(dpi) => Some(dpi),
+            None => {
+                eprintln!("The monitor doesn't report its physical size, give --dpi a number");
+                return 1;
+            }
+        };
+    }
+    if metadata {
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |n| n.as_secs() as i64);
+        encode_options.capture_info = Some(format::CaptureInfo {
+            timestamp: now,
+            window,
+            version: VERSION,
+        });
+    }
+
+    let cursor = if matches.opt_present("no-cursor") {
+        false
+    } else {
+        // Contact sheets and stitched areas have no single cursor position to draw at
+        matches.opt_present("cursor")
+            || (config.cursor == Some(true) && contact_sheet.is_none() && stitch.is_none())
+    };
+    if cursor {
+        match display.get_cursor_image() {
+            Some((position, hotspot, cursor)) => {
+                let (cursor, hotspot) = match (cursor_size, pointer_scale) {
+                    // A fixed size is the same on every machine, whatever their cursor theme
+                    (Some(n), _) => effects::resize_cursor(
+                        &cursor,
+                        hotspot,
+                        util::fit_size(cursor.dimensions(), (n, n)),
+                        imageops::FilterType::Triangle,
+                    ),
+                    // Nearest neighbour keeps the edges crisp
+                    (None, Some(n)) => match (
+                        cursor.width().checked_mul(n),
+                        cursor.height().checked_mul(n),
+                    ) {
+                        (Some(w), Some(h)) => effects::resize_cursor(
+                            &cursor,
+                            hotspot,
+                            (w, h),
+                            imageops::FilterType::Nearest,
+                        ),
+                        _ => {
+                            eprintln!("The cursor is too large to scale, drawing it as is");
+                            (cursor, hotspot)
+                        }
+                    },
+                    (None, None) => (cursor, hotspot),
+                };
+                util::composite_over(
+                    &mut image,
+                    &cursor,
+                    position.x - hotspot.x - capture_rect.x,
+                    position.y - hotspot.y - capture_rect.y,
+                )
+            }
+            None => eprintln!("XFixes is not available, capturing without the cursor"),
+        }
+    }
+
+    let mask_start = time::Instant::now();
+
+    // When capturing the root window, attempt to mask the off-screen areas. There is nothing to
+    // mask when the selection fits on a single screen, even if other screens overlap it.
+    if window == root
+        && stack.is_none()
+        && contact_sheet.is_none()
+        && stitch.is_none()
+        && !screen_rects.iter().any(|s| s.contains_rect(sel))
+    {
+        let on_selection = screen_rects
             .iter()
-            .filter_map(|s| s.intersection(sel))
-            .collect();
+            .filter(|s| s.intersection(sel).is_some())
+            .count();
 
         // No point in masking if we're only capturing one screen
-        if screens.len() > 1 {
-            let mut masked = RgbaImage::from_pixel(sel.w as u32, sel.h as u32, Rgba([0, 0, 0, 0]));
-
-            for screen in screens {
-                // Subimage is relative to the captured area
-                let sub = util::Rect {
-                    x: screen.x - sel.x,
-                    y: screen.y - sel.y,
-                    w: screen.w,
-                    h: screen.h,
-                };
+        if on_selection > 1 {
+            image = util::mask_screens(&image, sel, &screen_rects);
+        }
+    }
+
+    if let Some(rects) = &span_rects {
+        let keep: Vec<util::Rect> = rects
+            .iter()
+            .map(|r| util::Rect {
+                x: r.x - sel.x,
+                y: r.y - sel.y,
+                ..*r
+            })
+            .collect();
+        util::mask_outside(&mut image, &keep);
+    }
+
+    if let Some(id) = isolate {
+        let rect = match display.get_window_geometry(id) {
+            Some(r) => r,
+            None => {
+                eprintln!("Failed to get geometry of window {id:#x}");
+                return 1;
+            }
+        };
+
+        // Window geometry is relative to the root window, make it relative to the captured area
+        util::mask_outside(
+            &mut image,
+            &[util::Rect {
+                x: rect.x - sel.x,
+                y: rect.y - sel.y,
+                w: rect.w,
+                h: rect.h,
+            }],
+        );
+    }
+    timings.mask += mask_start.elapsed();
+
+    for &(rect, method) in &censored {
+        effects::censor(&mut image, rect, method);
+    }
+
+    if matches.opt_present("trim-to-content") {
+        match util::content_rect(&image) {
+            Some(r) => {
+                image = imageops::crop_imm(&image, r.x as u32, r.y as u32, r.w as u32, r.h as u32)
+                    .to_image();
+            }
+            None => eprintln!("The image is a single color, not trimming it"),
+        }
+    }
 
-                let view = image.view(sub.x as u32, sub.y as u32, sub.w as u32, sub.h as u32);
-                masked
-                    .copy_from(&*view, sub.x as u32, sub.y as u32)
-                    .expect("Failed to copy sub-image");
+    // Rotate before scaling, so that --fit applies to the final orientation
+    let rotation = match rotation {
+        Some(r) => r,
+        None => {
+            // RandR rotations are counter-clockwise
+            let center = capture_rect.center();
+            match display
+                .get_screens()
+                .and_then(|s| s.into_iter().find(|s| s.rect.contains(center)))
+            {
+                Some(screen) => (360 - screen.rotation) % 360,
+                None => {
+                    eprintln!("Failed to find the screen of the capture, not rotating");
+                    0
+                }
             }
+        }
+    };
+    image = effects::rotate(image, rotation);
+    if let Some(flip) = flip {
+        effects::flip(&mut image, flip);
+    }
+
+    if let Some((spacing, color)) = grid {
+        effects::draw_grid(&mut image, spacing, grid_major, color, grid_origin);
+    }
 
-            image = masked;
+    let target = match (scale, resize) {
+        (Some(factor), _) => {
+            let (w, h) = image.dimensions();
+            // Kept as floats until checked, casting saturates
+            let scaled = |d: u32| (d as f64 * factor).round().max(1.0);
+            Some((scaled(w), scaled(h)))
+        }
+        (None, Some(size)) => {
+            let (w, h) = util::resize_dimensions(image.dimensions(), size);
+            Some((w as f64, h as f64))
+        }
+        (None, None) => None,
+    };
+    if let Some((w, h)) = target {
+        if w > MAX_IMAGE_SIZE as f64 || h > MAX_IMAGE_SIZE as f64 {
+            eprintln!(
+                "The scaled image would be {w}x{h}, larger than {MAX_IMAGE_SIZE}x{MAX_IMAGE_SIZE}"
+            );
+            return 1;
         }
+        let (w, h) = (w as u32, h as u32);
+        image = if matches.opt_present("linear-downscale") {
+            color::resize_linear(&image, w, h, scale_filter)
+        } else {
+            imageops::resize(&image, w, h, scale_filter)
+        };
+    }
+
+    if let Some(size) = fit {
+        let background = encode_options.background.unwrap_or(Rgba([0, 0, 0, 0]));
+        image = effects::fit(&image, size, background);
+    }
+
+    if let Some((mark, position, opacity)) = &watermark {
+        effects::watermark(&mut image, mark, *position, *opacity);
+    }
+
+    if let Some(bits) = dither {
+        effects::dither(&mut image, bits, dither_method);
+    }
+
+    if let Some(baseline) = &baseline {
+        let (diff, changed) = match util::diff_image(&image, baseline) {
+            Some(d) => d,
+            None => {
+                let (w, h) = image.dimensions();
+                eprintln!(
+                    "Cannot compare the {}x{} capture to the {}x{} baseline",
+                    w,
+                    h,
+                    baseline.width(),
+                    baseline.height()
+                );
+                return 1;
+            }
+        };
+        let total = image.width() as u64 * image.height() as u64;
+        eprintln!(
+            "{:.2}% changed ({} of {} pixels)",
+            changed as f64 * 100.0 / total as f64,
+            changed,
+            total
+        );
+        image = diff;
     }
 
     let ts_path = {
@@ -228,17 +3453,158 @@ fn run() -> i32 {
             Ok(n) => n.as_secs(),
             Err(_) => 0,
         };
-        format!("{now}.{output_ext}")
+        match frame {
+            Some(n) => format!("{now}-{n:04}.{}", output_format.info().name),
+            None => format!("{now}.{}", output_format.info().name),
+        }
     };
-    let path = match matches.free.get(0) {
+    let template = filename_template.or(config.filename.clone());
+    let expanded;
+    let path = match output {
         Some(p) => p,
-        None => {
-            eprintln!("No output specified, defaulting to {ts_path}");
-            ts_path.as_str()
+        None if base64 || (clipboard && !matches.opt_present("filename")) => "-",
+        None => match &template {
+            Some(t) => {
+                expanded = match expand_filename(t, window, image.dimensions(), frame) {
+                    Some(e) => e,
+                    None => return 1,
+                };
+                expanded.as_str()
+            }
+            None => {
+                eprintln!("No output specified, defaulting to {ts_path}");
+                ts_path.as_str()
+            }
+        },
+    };
+
+    let mut pages = Vec::new();
+    if matches.opt_present("append") {
+        if output_format != Format::Tiff {
+            eprintln!("--append is only supported for TIFF output, ignoring it");
+        } else if path == "-" {
+            eprintln!("Cannot append to stdout, ignoring --append");
+        } else {
+            match File::open(path) {
+                Ok(f) => match multipage::read_tiff_pages(io::BufReader::new(f)) {
+                    Ok(p) => pages = p,
+                    Err(e) => {
+                        eprintln!("Cannot append to {path}, it is not a readable TIFF file: {e}");
+                        return 1;
+                    }
+                },
+                // Appending to a file that doesn't exist yet simply creates it
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    eprintln!("Failed to open {path}: {e}");
+                    return 1;
+                }
+            }
+        }
+    }
+
+    let encoding = if data_uri {
+        Encoding::DataUri
+    } else if base64 {
+        Encoding::Base64
+    } else {
+        Encoding::Binary
+    };
+
+    // Extra outputs are written first, as the main one may run on another thread
+    let mut also_failed = false;
+    for (format, file) in &also {
+        let written = encode_output(&image, &[], *format, &encode_options, Encoding::Binary)
+            .and_then(|encoded| Ok(fs::write(file, encoded)?));
+        if let Err(e) = written {
+            eprintln!("Failed to write {file}: {e}");
+            also_failed = true;
+        }
+    }
+
+    let dimensions = image.dimensions();
+    let encode = move || match limit_bytes {
+        Some(limit) => encode_with_limit(
+            &image,
+            &pages,
+            output_format,
+            &encode_options,
+            encoding,
+            limit,
+            lossy_fallback,
+        ),
+        None => encode_output(&image, &pages, output_format, &encode_options, encoding)
+            .map(|e| (e, output_format, encode_options)),
+    };
+
+    let encode_start = time::Instant::now();
+    let encoded = match max_time {
+        Some(max_time) => {
+            let remaining = match max_time.checked_sub(capture_start.elapsed()) {
+                Some(r) => r,
+                None => {
+                    eprintln!("Capture took longer than --max-time, aborting");
+                    return EX_TIMEOUT;
+                }
+            };
+
+            // Encode on a worker thread so we can stop waiting for it, it dies with the process
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                let _ = tx.send(encode());
+            });
+            match rx.recv_timeout(remaining) {
+                Ok(r) => r,
+                Err(_) => {
+                    eprintln!("Encoding took longer than --max-time, aborting");
+                    return EX_TIMEOUT;
+                }
+            }
+        }
+        None => encode(),
+    };
+    timings.encode = encode_start.elapsed();
+    let (encoded, used_format, used_options) = match encoded {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("Failed to encode image: {e}");
+            return 1;
+        }
+    };
+
+    if used_format == Format::Raw {
+        // Nothing in the data itself tells how to read it back
+        let (w, h) = dimensions;
+        eprintln!("Raw RGBA: width {w}, height {h}, stride {} bytes", w * 4);
+    }
+
+    let fallback_path;
+    let path = if used_format != output_format {
+        eprintln!(
+            "Fell back to {} to stay under {} bytes",
+            used_format.info().name,
+            limit_bytes.unwrap_or_default()
+        );
+        if matches.free.is_empty() && template.is_none() && !base64 && !clipboard {
+            fallback_path = ts_path.replace(output_format.info().name, used_format.info().name);
+            eprintln!("Writing to {fallback_path} instead");
+            fallback_path.as_str()
+        } else {
+            path
         }
+    } else {
+        path
     };
+    if used_options.quality != encode_options.quality {
+        if let Some(q) = used_options.quality {
+            eprintln!("Lowered quality to {q} to stay under the size limit");
+        }
+    }
 
-    let writer: Box<dyn io::Write> = if path == "-" {
+    // With a file, it is written in full before serving the clipboard, which blocks
+    let writer: Box<dyn io::Write> = if clipboard && path == "-" {
+        Box::new(io::sink())
+    } else if path == "-" {
         Box::new(io::stdout())
     } else {
         match File::create(Path::new(&path)) {
@@ -250,22 +3616,48 @@ fn run() -> i32 {
         }
     };
 
-    match output_format {
-        ImageOutputFormat::Png => {
-            let encoder = codecs::png::PngEncoder::new(writer);
-            util::write_image_buffer_with_encoder(&image, encoder)
-        }
-        ImageOutputFormat::Pnm(subtype) => {
-            let encoder = codecs::pnm::PnmEncoder::new(writer).with_subtype(subtype);
-            util::write_image_buffer_with_encoder(&image, encoder)
+    #[cfg(feature = "hash")]
+    let mut hasher = hash::Sha256::default();
+    #[cfg(feature = "hash")]
+    let writer: Box<dyn io::Write + '_> = if print_hash {
+        Box::new(hash::HashingWriter::new(writer, &mut hasher))
+    } else {
+        writer
+    };
+    let mut writer = writer;
+
+    writer.write_all(&encoded).expect("Failed to write output");
+    writer.flush().expect("Failed to write output");
+    drop(writer);
+
+    #[cfg(feature = "hash")]
+    if print_hash {
+        eprintln!("{}  {path}", hash::hex_digest(hasher));
+    }
+
+    if matches.opt_present("timing-json") {
+        eprintln!("{}", timings.to_json(dimensions));
+    }
+
+    // Clipboard contents are handed out on request, so this waits for pastes
+    if clipboard {
+        let mime_type = used_format.info().mime_type;
+        if display
+            .serve_clipboard(&encoded, mime_type, clipboard_timeout)
+            .is_none()
+        {
+            eprintln!("Failed to serve the clipboard");
+            return 1;
         }
-        _ => unreachable!(),
     }
-    .expect("Failed to write output");
 
-    0
+    if also_failed {
+        1
+    } else {
+        0
+    }
 }
 
 fn main() {
-    process::exit(run());
+    process::exit(run(None));
 }
@@ -0,0 +1,94 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+
+use image::DynamicImage;
+use image::ImageBuffer;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::encoder::{colortype, TiffEncoder};
+use tiff::{ColorType, TiffError, TiffResult, TiffUnsupportedError};
+
+/// Read every page of a multi-page TIFF.
+///
+/// Only 8-bit grayscale, RGB and RGBA pages are supported, which covers everything shotgun
+/// writes itself.
+pub fn read_tiff_pages(reader: impl io::Read + io::Seek) -> TiffResult<Vec<DynamicImage>> {
+    let mut decoder = Decoder::new(reader)?;
+    let mut pages = Vec::new();
+
+    loop {
+        let (w, h) = decoder.dimensions()?;
+        let color_type = decoder.colortype()?;
+        let page = match (color_type, decoder.read_image()?) {
+            (ColorType::Gray(8), DecodingResult::U8(buf)) => {
+                ImageBuffer::from_raw(w, h, buf).map(DynamicImage::ImageLuma8)
+            }
+            (ColorType::RGB(8), DecodingResult::U8(buf)) => {
+                ImageBuffer::from_raw(w, h, buf).map(DynamicImage::ImageRgb8)
+            }
+            (ColorType::RGBA(8), DecodingResult::U8(buf)) => {
+                ImageBuffer::from_raw(w, h, buf).map(DynamicImage::ImageRgba8)
+            }
+            _ => None,
+        };
+        pages.push(page.ok_or(TiffError::UnsupportedError(
+            TiffUnsupportedError::UnsupportedColorType(color_type),
+        ))?);
+
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image()?;
+    }
+
+    Ok(pages)
+}
+
+/// Write `pages` as a multi-page TIFF, one IFD per page.
+pub fn write_tiff_pages(
+    writer: impl io::Write + io::Seek,
+    pages: &[DynamicImage],
+) -> TiffResult<()> {
+    let mut encoder = TiffEncoder::new(writer)?;
+
+    for page in pages {
+        let (w, h) = (page.width(), page.height());
+        match page {
+            DynamicImage::ImageLuma8(buf) => encoder.write_image::<colortype::Gray8>(w, h, buf)?,
+            DynamicImage::ImageRgb8(buf) => encoder.write_image::<colortype::RGB8>(w, h, buf)?,
+            DynamicImage::ImageRgba8(buf) => encoder.write_image::<colortype::RGBA8>(w, h, buf)?,
+            _ => encoder.write_image::<colortype::RGBA8>(w, h, &page.to_rgba8())?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use image::Rgba;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_tiff_pages_round_trip() {
+        let pages = vec![
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(3, 2, Rgba([1, 2, 3, 4]))),
+            DynamicImage::ImageRgb8(ImageBuffer::from_pixel(1, 5, image::Rgb([5, 6, 7]))),
+        ];
+
+        let mut buf = io::Cursor::new(Vec::new());
+        write_tiff_pages(&mut buf, &pages).unwrap();
+        buf.set_position(0);
+
+        assert_eq!(read_tiff_pages(buf).unwrap(), pages);
+    }
+
+    #[test]
+    fn test_read_tiff_pages_rejects_other_files() {
+        assert!(read_tiff_pages(io::Cursor::new(b"\x89PNG\r\n\x1a\n".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::time::Duration;
+use std::time::Instant;
+
+/// Time spent in each stage of a capture, for `--timing-json`.
+#[derive(Clone, Debug, Default)]
+pub struct Timings {
+    /// Connecting to the X server
+    pub open: Duration,
+    /// Waiting for GetImage replies
+    pub get_image: Duration,
+    /// Converting X images to RGBA
+    pub convert: Duration,
+    /// Masking off-screen areas and other windows
+    pub mask: Duration,
+    /// Encoding the output file
+    pub encode: Duration,
+}
+
+/// Run `f`, adding the time it took to `stage`.
+pub fn measure<T>(stage: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    *stage += start.elapsed();
+    result
+}
+
+impl Timings {
+    /// Format the timings in milliseconds as a single-line JSON object, along with the size of
+    /// the image.
+    pub fn to_json(&self, (width, height): (u32, u32)) -> String {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        format!(
+            "{{\"open_ms\":{:.3},\"get_image_ms\":{:.3},\"convert_ms\":{:.3},\
+                \"mask_ms\":{:.3},\"encode_ms\":{:.3},\"width\":{},\"height\":{}}}",
+            ms(self.open),
+            ms(self.get_image),
+            ms(self.convert),
+            ms(self.mask),
+            ms(self.encode),
+            width,
+            height,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let mut timings = Timings {
+            open: Duration::from_micros(1500),
+            get_image: Duration::from_millis(20),
+            ..Default::default()
+        };
+        assert_eq!(measure(&mut timings.encode, || 42), 42);
+        timings.encode = Duration::from_nanos(250);
+
+        assert_eq!(
+            timings.to_json((1920, 1080)),
+            "{\"open_ms\":1.500,\"get_image_ms\":20.000,\"convert_ms\":0.000,\
+                \"mask_ms\":0.000,\"encode_ms\":0.000,\"width\":1920,\"height\":1080}"
+        );
+    }
+}
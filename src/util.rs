@@ -1,4 +1,12 @@
 use std::cmp;
+use std::fmt;
+
+use image::GenericImage;
+use image::GenericImageView;
+use image::Rgb;
+use image::RgbImage;
+use image::Rgba;
+use image::RgbaImage;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Rect {
@@ -8,7 +16,14 @@ pub struct Rect {
     pub h: i32,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Formats as `WxH+X+Y`, which [`parse_geometry`] reads back.
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.w, self.h, self.x, self.y)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Point {
     pub x: i32,
     pub y: i32,
@@ -33,9 +48,83 @@ impl Rect {
         }
     }
 
+    /// Smallest rectangle containing both pixels `a` and `b`, in any order.
+    pub fn from_corners(a: Point, b: Point) -> Rect {
+        let x = cmp::min(a.x, b.x);
+        let y = cmp::min(a.y, b.y);
+        Rect {
+            x,
+            y,
+            w: (a.x - b.x).abs() + 1,
+            h: (a.y - b.y).abs() + 1,
+        }
+    }
+
+    /// Smallest rectangle containing both `self` and `other`.
+    pub fn union(&self, other: Rect) -> Rect {
+        let x = cmp::min(self.x, other.x);
+        let y = cmp::min(self.y, other.y);
+        Rect {
+            x,
+            y,
+            w: cmp::max(self.x + self.w, other.x + other.w) - x,
+            h: cmp::max(self.y + self.h, other.y + other.h) - y,
+        }
+    }
+
+    /// Center point, rounded towards the top-left for odd sizes, whose center falls mid-pixel.
+    pub fn center(&self) -> Point {
+        Point {
+            x: self.x + self.w.div_euclid(2),
+            y: self.y + self.h.div_euclid(2),
+        }
+    }
+
+    /// Same-sized rectangle moved so that its [`center`](Rect::center) matches that of `bounds`.
+    pub fn centered_in(&self, bounds: Rect) -> Rect {
+        let center = bounds.center();
+        Rect {
+            x: center.x - self.w.div_euclid(2),
+            y: center.y - self.h.div_euclid(2),
+            w: self.w,
+            h: self.h,
+        }
+    }
+
     pub fn contains(&self, pos: Point) -> bool {
         pos.x >= self.x && pos.x < self.x + self.w && pos.y >= self.y && pos.y < self.y + self.h
     }
+
+    /// Whether `other` lies entirely within this rectangle.
+    pub fn contains_rect(&self, other: Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.x + other.w <= self.x + self.w
+            && other.y + other.h <= self.y + self.h
+    }
+
+    /// Describe the axes along which `other` doesn't fit within this rectangle, as its range of
+    /// pixels against ours, e.g. `x 1800..2100 is outside 0..1920`.
+    pub fn overflow(&self, other: Rect) -> Vec<String> {
+        let axes = [
+            ("x", other.x, other.w, self.x, self.w),
+            ("y", other.y, other.h, self.y, self.h),
+        ];
+        axes.iter()
+            .filter(|&&(_, start, len, bound, bound_len)| {
+                start < bound || start + len > bound + bound_len
+            })
+            .map(|&(axis, start, len, bound, bound_len)| {
+                format!(
+                    "{axis} {}..{} is outside {}..{}",
+                    start,
+                    start + len,
+                    bound,
+                    bound + bound_len
+                )
+            })
+            .collect()
+    }
 }
 
 pub fn parse_int<T: num_traits::Num>(string: &str) -> Result<T, T::FromStrRadixErr> {
@@ -50,6 +139,450 @@ pub fn parse_int<T: num_traits::Num>(string: &str) -> Result<T, T::FromStrRadixE
     }
 }
 
+/// Parse an `ID:WxH+X+Y` window region, the geometry being relative to the window.
+pub fn parse_window_region(s: &str) -> Option<(u32, Rect)> {
+    let (id, geometry) = s.split_once(':')?;
+    Some((parse_int(id).ok()?, parse_geometry(geometry)?))
+}
+
+/// Blend `src` over `dst` (the Porter-Duff "over" operator) using straight alpha.
+pub fn blend_over(dst: Rgba<u8>, src: Rgba<u8>) -> Rgba<u8> {
+    let src_a = src[3] as u32;
+    let dst_a = dst[3] as u32 * (255 - src_a) / 255;
+    let out_a = src_a + dst_a;
+    if out_a == 0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+
+    let channel =
+        |i: usize| ((src[i] as u32 * src_a + dst[i] as u32 * dst_a + out_a / 2) / out_a) as u8;
+    Rgba([channel(0), channel(1), channel(2), out_a as u8])
+}
+
+/// Composite `image` over an opaque `background`, dropping the alpha channel.
+pub fn flatten(image: &RgbaImage, background: Rgba<u8>) -> RgbImage {
+    let background = Rgba([background[0], background[1], background[2], 255]);
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, _] = blend_over(background, *image.get_pixel(x, y)).0;
+        Rgb([r, g, b])
+    })
+}
+
+/// Alpha-composite `src` onto `dst` with its top-left corner at (`x`, `y`), clipped to `dst`.
+pub fn composite_over(dst: &mut RgbaImage, src: &RgbaImage, x: i32, y: i32) {
+    for (sx, sy, &pixel) in src.enumerate_pixels() {
+        let (dx, dy) = (x + sx as i32, y + sy as i32);
+        if dx < 0 || dy < 0 || dx >= dst.width() as i32 || dy >= dst.height() as i32 {
+            continue;
+        }
+
+        let dst_pixel = dst.get_pixel_mut(dx as u32, dy as u32);
+        *dst_pixel = blend_over(*dst_pixel, pixel);
+    }
+}
+
+/// Broken-down time, for file name templates and metadata.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i32,
+    /// 1 to 12
+    pub month: u32,
+    /// 1 to 31
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+/// Convert a Unix timestamp to the local time zone.
+#[cfg(unix)]
+pub fn local_time(timestamp: i64) -> Option<DateTime> {
+    let time = timestamp as libc::time_t;
+    // SAFETY: localtime_r only writes to tm, which is a plain C struct for which zeroes are valid
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(DateTime {
+        year: tm.tm_year + 1900,
+        month: tm.tm_mon as u32 + 1,
+        day: tm.tm_mday as u32,
+        hour: tm.tm_hour as u32,
+        minute: tm.tm_min as u32,
+        second: tm.tm_sec as u32,
+    })
+}
+
+/// Without a time zone database, fall back to UTC.
+#[cfg(not(unix))]
+pub fn local_time(timestamp: i64) -> Option<DateTime> {
+    Some(utc_time(timestamp))
+}
+
+/// Convert a Unix timestamp to UTC.
+pub fn utc_time(timestamp: i64) -> DateTime {
+    let days = timestamp.div_euclid(86400);
+    let secs = timestamp.rem_euclid(86400) as u32;
+
+    // Civil date from days since the epoch, in eras of 400 years starting on March 1st
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = (year_of_era + era * 400) as i32 + i32::from(month <= 2);
+
+    DateTime {
+        year,
+        month,
+        day,
+        hour: secs / 3600,
+        minute: secs / 60 % 60,
+        second: secs % 60,
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// ISO 8601, e.g. `2024-03-07T09:05:00`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Expand the tokens of a file name template: `%Y`, `%m`, `%d`, `%H`, `%M` and `%S` as in
+/// `strftime`, `%wid` for the captured window ID, `%wxh` for the image size, `%n` for the frame
+/// number (padded to 4 digits) and `%%` for a literal `%`. Other tokens are left alone.
+pub fn expand_template(
+    template: &str,
+    time: DateTime,
+    window: u32,
+    size: (u32, u32),
+    frame: u32,
+) -> String {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let tokens: [(&str, String); 10] = [
+            ("%wid", format!("{window:#x}")),
+            ("%wxh", format!("{}x{}", size.0, size.1)),
+            ("%Y", format!("{:04}", time.year)),
+            ("%m", format!("{:02}", time.month)),
+            ("%d", format!("{:02}", time.day)),
+            ("%H", format!("{:02}", time.hour)),
+            ("%M", format!("{:02}", time.minute)),
+            ("%S", format!("{:02}", time.second)),
+            ("%n", format!("{frame:04}")),
+            ("%%", "%".to_string()),
+        ];
+        match tokens.iter().find(|(token, _)| rest.starts_with(token)) {
+            Some((token, value)) => {
+                expanded.push_str(value);
+                rest = &rest[token.len()..];
+            }
+            None => {
+                expanded.push('%');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Pixel density of `pixels` spread over `mm` millimetres, in dots per inch.
+pub fn dpi(pixels: i32, mm: u32) -> Option<u16> {
+    if pixels <= 0 || mm == 0 {
+        return None;
+    }
+    // Saturates rather than wrapping on absurd sizes
+    Some((pixels as f64 * 25.4 / mm as f64).round() as u16)
+}
+
+/// Colors that can be given by name instead of in hex, with their CSS values.
+pub const COLOR_NAMES: &[(&str, [u8; 4])] = &[
+    ("black", [0, 0, 0, 255]),
+    ("white", [255, 255, 255, 255]),
+    ("gray", [128, 128, 128, 255]),
+    ("grey", [128, 128, 128, 255]),
+    ("red", [255, 0, 0, 255]),
+    ("green", [0, 128, 0, 255]),
+    ("blue", [0, 0, 255, 255]),
+    ("yellow", [255, 255, 0, 255]),
+    ("cyan", [0, 255, 255, 255]),
+    ("magenta", [255, 0, 255, 255]),
+    ("transparent", [0, 0, 0, 0]),
+];
+
+/// Parse a color in hex notation: `RGB`, `RGBA`, `RRGGBB` or `RRGGBBAA`, with an optional `#`, or
+/// one of [`COLOR_NAMES`], ignoring case.
+pub fn parse_color(s: &str) -> Option<Rgba<u8>> {
+    if let Some((_, color)) = COLOR_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(s)) {
+        return Some(Rgba(*color));
+    }
+
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let digits: Vec<u8> = hex
+        .chars()
+        .map(|c| c.to_digit(16).expect("Not a hex digit") as u8)
+        .collect();
+    let channels: Vec<u8> = match digits.len() {
+        3 | 4 => digits.iter().map(|d| d * 0x11).collect(),
+        6 | 8 => digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect(),
+        _ => return None,
+    };
+    let alpha = channels.get(3).copied().unwrap_or(255);
+    Some(Rgba([channels[0], channels[1], channels[2], alpha]))
+}
+
+/// Find the highest value in `lo..=hi` that passes `test` with a binary search, assuming that
+/// every value below a passing one also passes.
+pub fn highest_passing<E>(
+    lo: u8,
+    hi: u8,
+    mut test: impl FnMut(u8) -> Result<bool, E>,
+) -> Result<Option<u8>, E> {
+    if lo > hi || !test(lo)? {
+        return Ok(None);
+    }
+
+    // good always passes, bad is the lowest value known to fail (or past the end)
+    let (mut good, mut bad) = (lo as u16, hi as u16 + 1);
+    while bad - good > 1 {
+        let mid = (good + bad) / 2;
+        if test(mid as u8)? {
+            good = mid;
+        } else {
+            bad = mid;
+        }
+    }
+    Ok(Some(good as u8))
+}
+
+/// Parse a size of the form `<width>x<height>`, both of which must be positive.
+pub fn parse_size(s: &str) -> Option<(u32, u32)> {
+    let (w, h) = s.split_once(['x', 'X'])?;
+    let (w, h) = (w.parse().ok()?, h.parse().ok()?);
+    if w == 0 || h == 0 {
+        return None;
+    }
+    Some((w, h))
+}
+
+/// Parse a size of the form `<width>x<height>` where one of the dimensions may be 0 or left out
+/// (e.g. `800x0`, `800x` or `800`, and `x600`), to be computed from the aspect ratio.
+pub fn parse_resize(s: &str) -> Option<(u32, u32)> {
+    let (w, h) = s.split_once(['x', 'X']).unwrap_or((s, ""));
+    let parse = |d: &str| {
+        if d.is_empty() {
+            Some(0)
+        } else {
+            d.parse().ok()
+        }
+    };
+    match (parse(w)?, parse(h)?) {
+        (0, 0) => None,
+        size => Some(size),
+    }
+}
+
+/// Parse a scale factor given as a percentage, with or without a `%` sign.
+pub fn parse_percentage(s: &str) -> Option<f64> {
+    let percent: f64 = s.strip_suffix('%').unwrap_or(s).parse().ok()?;
+    if percent.is_finite() && percent > 0.0 {
+        Some(percent / 100.0)
+    } else {
+        None
+    }
+}
+
+/// Fill in the zero dimension of `target` (as returned by [`parse_resize`]) to keep the aspect
+/// ratio of `size`.
+pub fn resize_dimensions(size: (u32, u32), target: (u32, u32)) -> (u32, u32) {
+    let (w, h) = (size.0 as u64, size.1 as u64);
+    match target {
+        (0, th) => (cmp::max(1, (w * th as u64 + h / 2) / h) as u32, th),
+        (tw, 0) => (tw, cmp::max(1, (h * tw as u64 + w / 2) / w) as u32),
+        target => target,
+    }
+}
+
+/// Largest size with the aspect ratio of `size` that fits within `bounds`.
+pub fn fit_size(size: (u32, u32), bounds: (u32, u32)) -> (u32, u32) {
+    let (w, h) = (size.0 as u64, size.1 as u64);
+    let (bw, bh) = (bounds.0 as u64, bounds.1 as u64);
+
+    // Compare the aspect ratios without going through floats
+    if w * bh > h * bw {
+        (bounds.0, cmp::max(1, (h * bw + w / 2) / w) as u32)
+    } else {
+        (cmp::max(1, (w * bh + h / 2) / h) as u32, bounds.1)
+    }
+}
+
+/// Make every pixel of `image` that is outside of all the `keep` rectangles fully transparent.
+pub fn mask_outside(image: &mut RgbaImage, keep: &[Rect]) {
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let pos = Point {
+            x: x as i32,
+            y: y as i32,
+        };
+        if !keep.iter().any(|r| r.contains(pos)) {
+            *pixel = Rgba([0, 0, 0, 0]);
+        }
+    }
+}
+
+/// Copy the parts of `image`, a capture of `sel` on the root window, that are shown on one of the
+/// `screens`, leaving off-screen areas transparent.
+pub fn mask_screens(image: &RgbaImage, sel: Rect, screens: &[Rect]) -> RgbaImage {
+    let mut masked = RgbaImage::from_pixel(image.width(), image.height(), Rgba([0, 0, 0, 0]));
+
+    for screen in screens.iter().filter_map(|s| s.intersection(sel)) {
+        // Subimage is relative to the captured area, in both the capture and the output
+        let sub = Rect {
+            x: screen.x - sel.x,
+            y: screen.y - sel.y,
+            w: screen.w,
+            h: screen.h,
+        };
+
+        let view = image.view(sub.x as u32, sub.y as u32, sub.w as u32, sub.h as u32);
+        masked
+            .copy_from(&*view, sub.x as u32, sub.y as u32)
+            .expect("Failed to copy sub-image");
+    }
+
+    masked
+}
+
+/// Find the part of `image` left after cropping away solid borders.
+///
+/// The border color is the most common color along the edges of the image, rows and columns of
+/// that color are then peeled off each side. Returns `None` if the whole image is that color.
+pub fn content_rect(image: &RgbaImage) -> Option<Rect> {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return None;
+    }
+
+    let mut edge: Vec<Rgba<u8>> = (0..w)
+        .flat_map(|x| [*image.get_pixel(x, 0), *image.get_pixel(x, h - 1)])
+        .chain((0..h).flat_map(|y| [*image.get_pixel(0, y), *image.get_pixel(w - 1, y)]))
+        .collect();
+    edge.sort_unstable_by_key(|p| p.0);
+    let border = edge
+        .chunk_by(|a, b| a == b)
+        .max_by_key(|run| run.len())
+        .map(|run| run[0])?;
+
+    let row_is_border = |y: u32| (0..w).all(|x| *image.get_pixel(x, y) == border);
+    let top = (0..h).find(|&y| !row_is_border(y))?;
+    let bottom = (0..h).rev().find(|&y| !row_is_border(y))?;
+    let col_is_border = |x: u32| (top..=bottom).all(|y| *image.get_pixel(x, y) == border);
+    let left = (0..w).find(|&x| !col_is_border(x))?;
+    let right = (0..w).rev().find(|&x| !col_is_border(x))?;
+
+    Some(Rect {
+        x: left as i32,
+        y: top as i32,
+        w: (right - left + 1) as i32,
+        h: (bottom - top + 1) as i32,
+    })
+}
+
+/// Guess whether `image` is (nearly) entirely black, by sampling a grid of its pixels.
+///
+/// Transparent pixels are ignored, so masked-out areas don't count as black.
+pub fn is_black(image: &RgbaImage) -> bool {
+    const STEP: usize = 8;
+    // Channel values this dark count as black, to tolerate noise from dithering
+    const DARK: u8 = 0x10;
+    // Fraction of sampled pixels that may be brighter, e.g. for a mouse cursor
+    const TOLERANCE: f64 = 0.001;
+
+    let (mut samples, mut bright) = (0, 0);
+    for y in (0..image.height()).step_by(STEP) {
+        for x in (0..image.width()).step_by(STEP) {
+            let pixel = image.get_pixel(x, y);
+            if pixel[3] == 0 {
+                continue;
+            }
+            samples += 1;
+            if pixel[0] > DARK || pixel[1] > DARK || pixel[2] > DARK {
+                bright += 1;
+            }
+        }
+    }
+
+    bright as f64 <= samples as f64 * TOLERANCE
+}
+
+/// Estimate the fraction of pixels that differ between two images of the same size.
+///
+/// Only a grid of samples is compared, which is enough to notice tearing and much cheaper than
+/// comparing every pixel. Images of different sizes are considered entirely different.
+pub fn differing_fraction(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    const STEP: usize = 4;
+
+    if a.dimensions() != b.dimensions() {
+        return 1.0;
+    }
+
+    let (mut samples, mut differing) = (0, 0);
+    for y in (0..a.height()).step_by(STEP) {
+        for x in (0..a.width()).step_by(STEP) {
+            samples += 1;
+            if a.get_pixel(x, y) != b.get_pixel(x, y) {
+                differing += 1;
+            }
+        }
+    }
+
+    if samples == 0 {
+        0.0
+    } else {
+        differing as f64 / samples as f64
+    }
+}
+
+/// Compare `image` to `baseline` pixel by pixel, returning a visualization of the differences
+/// and the number of pixels that changed, or `None` if the images have different sizes.
+///
+/// Changed pixels are drawn in opaque red, over a faded grayscale version of `image`.
+pub fn diff_image(image: &RgbaImage, baseline: &RgbaImage) -> Option<(RgbaImage, u64)> {
+    if image.dimensions() != baseline.dimensions() {
+        return None;
+    }
+
+    let mut changed = 0;
+    let diff = RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y);
+        if pixel != baseline.get_pixel(x, y) {
+            changed += 1;
+            return Rgba([255, 0, 0, 255]);
+        }
+        let luma = (pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000;
+        let faded = (192 + luma / 4) as u8;
+        Rgba([faded, faded, faded, 255])
+    });
+    Some((diff, changed))
+}
+
 use image::EncodableLayout;
 pub fn write_image_buffer_with_encoder<P, Container>(
     image: &image::ImageBuffer<P, Container>,
@@ -68,6 +601,539 @@ where
     )
 }
 
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `data` as padded standard base64 (RFC 4648).
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn base64_decode(s: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        let digits: Vec<u32> = s
+            .bytes()
+            .filter(|&c| c != b'=')
+            .map(|c| BASE64_ALPHABET.iter().position(|&a| a == c).unwrap() as u32)
+            .collect();
+        for chunk in digits.chunks(4) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0, |n, (i, d)| n | d << (18 - 6 * i));
+            for i in 0..chunk.len() - 1 {
+                out.push((n >> (16 - 8 * i)) as u8);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_rect_union() {
+        let a = Rect {
+            x: 0,
+            y: 10,
+            w: 20,
+            h: 5,
+        };
+        let b = Rect {
+            x: -5,
+            y: 0,
+            w: 10,
+            h: 10,
+        };
+        let u = Rect {
+            x: -5,
+            y: 0,
+            w: 25,
+            h: 15,
+        };
+        assert_eq!(a.union(b), u);
+        assert_eq!(b.union(a), u);
+    }
+
+    #[test]
+    fn test_rect_from_corners() {
+        let rect = |x, y, w, h| Rect { x, y, w, h };
+        let p = |x, y| Point { x, y };
+        assert_eq!(Rect::from_corners(p(10, 20), p(12, 25)), rect(10, 20, 3, 6));
+        assert_eq!(Rect::from_corners(p(12, 25), p(10, 20)), rect(10, 20, 3, 6));
+        assert_eq!(Rect::from_corners(p(12, 20), p(10, 25)), rect(10, 20, 3, 6));
+        assert_eq!(Rect::from_corners(p(-5, 0), p(-5, 0)), rect(-5, 0, 1, 1));
+    }
+
+    #[test]
+    fn test_rect_center() {
+        let rect = |x, y, w, h| Rect { x, y, w, h };
+        assert_eq!(rect(0, 0, 4, 6).center(), Point { x: 2, y: 3 });
+        assert_eq!(rect(0, 0, 5, 7).center(), Point { x: 2, y: 3 });
+        assert_eq!(rect(-10, 10, 1, 1).center(), Point { x: -10, y: 10 });
+    }
+
+    #[test]
+    fn test_parse_window_region() {
+        let rect = |x, y, w, h| Rect { x, y, w, h };
+        assert_eq!(
+            parse_window_region("0x1e00004:200x100+10+20"),
+            Some((0x1e00004, rect(10, 20, 200, 100)))
+        );
+        assert_eq!(
+            parse_window_region("42:1x1+0+0"),
+            Some((42, rect(0, 0, 1, 1)))
+        );
+        assert_eq!(parse_window_region("42"), None);
+        assert_eq!(parse_window_region("foo:1x1+0+0"), None);
+        assert_eq!(parse_window_region("42:1x1"), None);
+    }
+
+    #[test]
+    fn test_rect_display() {
+        let rect = |x, y, w, h| Rect { x, y, w, h };
+        assert_eq!(rect(10, 20, 300, 400).to_string(), "300x400+10+20");
+        for r in [rect(10, 20, 300, 400), rect(-1920, -5, 1920, 1080)] {
+            assert_eq!(parse_geometry(&r.to_string()), Some(r));
+        }
+    }
+
+    #[test]
+    fn test_rect_contains_rect() {
+        let rect = |x, y, w, h| Rect { x, y, w, h };
+        let screen = rect(1920, 0, 1920, 1080);
+        assert!(screen.contains_rect(screen));
+        assert!(screen.contains_rect(rect(2000, 100, 300, 200)));
+        assert!(!screen.contains_rect(rect(1900, 100, 300, 200)));
+        assert!(!screen.contains_rect(rect(3800, 1000, 100, 100)));
+    }
+
+    #[test]
+    fn test_rect_overflow() {
+        let rect = |x, y, w, h| Rect { x, y, w, h };
+        let screen = rect(0, 0, 1920, 1080);
+        assert!(screen.overflow(rect(100, 100, 300, 200)).is_empty());
+        assert_eq!(
+            screen.overflow(rect(1800, 100, 300, 200)),
+            ["x 1800..2100 is outside 0..1920"]
+        );
+        assert_eq!(
+            screen.overflow(rect(-10, 1000, 100, 100)),
+            [
+                "x -10..90 is outside 0..1920",
+                "y 1000..1100 is outside 0..1080"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rect_centered_in() {
+        let rect = |x, y, w, h| Rect { x, y, w, h };
+        let bounds = rect(10, 20, 100, 50);
+        assert_eq!(rect(0, 0, 50, 10).centered_in(bounds), rect(35, 40, 50, 10));
+        // Odd sizes can't be centered exactly, the centers still line up
+        assert_eq!(rect(0, 0, 51, 11).centered_in(bounds), rect(35, 40, 51, 11));
+        assert_eq!(
+            rect(0, 0, 4, 4).centered_in(rect(0, 0, 5, 5)),
+            rect(0, 0, 4, 4)
+        );
+        // Larger than the bounds
+        assert_eq!(
+            rect(0, 0, 104, 50).centered_in(bounds),
+            rect(8, 20, 104, 50)
+        );
+    }
+
+    #[test]
+    fn test_blend_over() {
+        let dst = Rgba([0, 0, 255, 255]);
+        assert_eq!(
+            blend_over(dst, Rgba([255, 0, 0, 255])),
+            Rgba([255, 0, 0, 255])
+        );
+        assert_eq!(blend_over(dst, Rgba([255, 0, 0, 0])), dst);
+        assert_eq!(
+            blend_over(dst, Rgba([255, 0, 0, 128])),
+            Rgba([128, 0, 127, 255])
+        );
+        assert_eq!(
+            blend_over(Rgba([0, 0, 0, 0]), Rgba([10, 20, 30, 40])),
+            Rgba([10, 20, 30, 40])
+        );
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut image = RgbaImage::from_pixel(2, 1, Rgba([10, 20, 30, 255]));
+        image.put_pixel(1, 0, Rgba([255, 0, 0, 0]));
+        let flat = flatten(&image, Rgba([0, 0, 255, 0]));
+        assert_eq!(flat.get_pixel(0, 0), &Rgb([10, 20, 30]));
+        assert_eq!(flat.get_pixel(1, 0), &Rgb([0, 0, 255]));
+    }
+
+    #[test]
+    fn test_composite_over_clips() {
+        let mut dst = RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 255]));
+        let src = RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 255]));
+        composite_over(&mut dst, &src, 1, -1);
+        assert_eq!(dst.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(dst.get_pixel(1, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(dst.get_pixel(0, 1), &Rgba([0, 0, 0, 255]));
+        assert_eq!(dst.get_pixel(1, 1), &Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1920x1080"), Some((1920, 1080)));
+        assert_eq!(parse_size("16X9"), Some((16, 9)));
+        assert_eq!(parse_size("0x10"), None);
+        assert_eq!(parse_size("10x"), None);
+        assert_eq!(parse_size("-1x10"), None);
+        assert_eq!(parse_size("10"), None);
+    }
+
+    #[test]
+    fn test_expand_template() {
+        let time = DateTime {
+            year: 2024,
+            month: 3,
+            day: 7,
+            hour: 9,
+            minute: 5,
+            second: 0,
+        };
+        assert_eq!(
+            expand_template("shots/%Y-%m-%d/%H%M%S.png", time, 0x1e00004, (800, 600), 1),
+            "shots/2024-03-07/090500.png"
+        );
+        assert_eq!(
+            expand_template("%wid_%wxh.png", time, 0x1e00004, (800, 600), 1),
+            "0x1e00004_800x600.png"
+        );
+        assert_eq!(expand_template("100%%_%q%", time, 1, (1, 1), 1), "100%_%q%");
+        assert_eq!(
+            expand_template("frame-%n.png", time, 1, (1, 1), 42),
+            "frame-0042.png"
+        );
+        assert_eq!(
+            expand_template("plain.png", time, 1, (1, 1), 1),
+            "plain.png"
+        );
+    }
+
+    #[test]
+    fn test_utc_time() {
+        assert_eq!(utc_time(0).to_string(), "1970-01-01T00:00:00");
+        assert_eq!(utc_time(1_709_802_300).to_string(), "2024-03-07T09:05:00");
+        // Leap day, and the day before the epoch
+        assert_eq!(utc_time(951_825_599).to_string(), "2000-02-29T11:59:59");
+        assert_eq!(utc_time(-1).to_string(), "1969-12-31T23:59:59");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_time() {
+        let time = local_time(0).unwrap();
+        // Whatever the time zone, the epoch is within a day of 1970-01-01
+        assert!(time.year == 1969 || time.year == 1970);
+        assert!(time.hour < 24 && time.minute < 60 && time.second < 60);
+    }
+
+    #[test]
+    fn test_parse_resize() {
+        assert_eq!(parse_resize("800x600"), Some((800, 600)));
+        assert_eq!(parse_resize("800x0"), Some((800, 0)));
+        assert_eq!(parse_resize("800x"), Some((800, 0)));
+        assert_eq!(parse_resize("800"), Some((800, 0)));
+        assert_eq!(parse_resize("x600"), Some((0, 600)));
+        assert_eq!(parse_resize("0x0"), None);
+        assert_eq!(parse_resize("x"), None);
+        assert_eq!(parse_resize("-1x10"), None);
+    }
+
+    #[test]
+    fn test_parse_percentage() {
+        assert_eq!(parse_percentage("50%"), Some(0.5));
+        assert_eq!(parse_percentage("150"), Some(1.5));
+        assert_eq!(parse_percentage("0%"), None);
+        assert_eq!(parse_percentage("-10%"), None);
+        assert_eq!(parse_percentage("half"), None);
+    }
+
+    #[test]
+    fn test_resize_dimensions() {
+        assert_eq!(resize_dimensions((1920, 1080), (800, 600)), (800, 600));
+        assert_eq!(resize_dimensions((1920, 1080), (960, 0)), (960, 540));
+        assert_eq!(resize_dimensions((1920, 1080), (0, 540)), (960, 540));
+        assert_eq!(resize_dimensions((10000, 1), (100, 0)), (100, 1));
+    }
+
+    #[test]
+    fn test_fit_size() {
+        // Pillarbox
+        assert_eq!(fit_size((1920, 1080), (1080, 1080)), (1080, 608));
+        // Letterbox
+        assert_eq!(fit_size((1080, 1920), (1080, 1080)), (608, 1080));
+        // Upscaling, same aspect ratio
+        assert_eq!(fit_size((16, 9), (1920, 1080)), (1920, 1080));
+        // Never collapse to nothing
+        assert_eq!(fit_size((10000, 1), (100, 100)), (100, 1));
+    }
+
+    #[test]
+    fn test_mask_screens() {
+        // Two screens of different heights side by side, and a selection that starts away from
+        // both of their origins and straddles the edge between them
+        let screens = [
+            Rect {
+                x: 0,
+                y: 0,
+                w: 8,
+                h: 6,
+            },
+            Rect {
+                x: 8,
+                y: 0,
+                w: 10,
+                h: 10,
+            },
+        ];
+        let sel = Rect {
+            x: 5,
+            y: 3,
+            w: 10,
+            h: 6,
+        };
+        let image = RgbaImage::from_fn(10, 6, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+
+        let masked = mask_screens(&image, sel, &screens);
+        assert_eq!(masked.dimensions(), image.dimensions());
+        for (x, y, pixel) in masked.enumerate_pixels() {
+            let on_screen = screens.iter().any(|s| {
+                s.contains(Point {
+                    x: sel.x + x as i32,
+                    y: sel.y + y as i32,
+                })
+            });
+            if on_screen {
+                assert_eq!(pixel, image.get_pixel(x, y), "at {x},{y}");
+            } else {
+                assert_eq!(*pixel, Rgba([0, 0, 0, 0]), "at {x},{y}");
+            }
+        }
+        // Below the shorter screen
+        assert_eq!(masked.get_pixel(0, 5)[3], 0);
+        assert_eq!(masked.get_pixel(3, 3)[3], 255);
+    }
+
+    #[test]
+    fn test_mask_outside() {
+        let opaque = Rgba([1, 2, 3, 255]);
+        let mut image = RgbaImage::from_pixel(4, 4, opaque);
+        let keep = Rect {
+            x: 1,
+            y: -1,
+            w: 2,
+            h: 3,
+        };
+        mask_outside(&mut image, &[keep]);
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if (1..3).contains(&x) && y < 2 {
+                assert_eq!(*pixel, opaque);
+            } else {
+                assert_eq!(*pixel, Rgba([0, 0, 0, 0]));
+            }
+        }
+
+        // Pixels in any of the rectangles are kept
+        let mut image = RgbaImage::from_pixel(4, 4, opaque);
+        let corner = |x, y| Rect { x, y, w: 1, h: 1 };
+        mask_outside(&mut image, &[corner(0, 0), corner(3, 3)]);
+        let kept: Vec<_> = image
+            .enumerate_pixels()
+            .filter(|(_, _, p)| **p == opaque)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(kept, [(0, 0), (3, 3)]);
+    }
+
+    #[test]
+    fn test_highest_passing() {
+        let below = |limit: u8| move |q: u8| Ok::<_, ()>(q <= limit);
+        assert_eq!(highest_passing(1, 100, below(42)), Ok(Some(42)));
+        assert_eq!(highest_passing(1, 100, below(100)), Ok(Some(100)));
+        assert_eq!(highest_passing(1, 100, below(1)), Ok(Some(1)));
+        assert_eq!(highest_passing(1, 100, below(0)), Ok(None));
+        assert_eq!(highest_passing(0, 255, below(255)), Ok(Some(255)));
+
+        let mut tests = 0;
+        let _ = highest_passing(1, 100, |q| {
+            tests += 1;
+            Ok::<_, ()>(q <= 73)
+        });
+        assert!(tests <= 8, "{} tests", tests);
+
+        assert_eq!(highest_passing(1, 100, |_| Err("failed")), Err("failed"));
+    }
+
+    #[test]
+    fn test_dpi() {
+        assert_eq!(dpi(1920, 508), Some(96));
+        assert_eq!(dpi(3840, 597), Some(163));
+        assert_eq!(dpi(1920, 0), None);
+        assert_eq!(dpi(0, 508), None);
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#ff8000"), Some(Rgba([255, 128, 0, 255])));
+        assert_eq!(parse_color("FF800080"), Some(Rgba([255, 128, 0, 128])));
+        assert_eq!(parse_color("#f80"), Some(Rgba([255, 136, 0, 255])));
+        assert_eq!(parse_color("f808"), Some(Rgba([255, 136, 0, 136])));
+        assert_eq!(parse_color("#ff80"), Some(Rgba([255, 255, 136, 0])));
+        assert_eq!(parse_color("#ff800"), None);
+        assert_eq!(parse_color("red"), Some(Rgba([255, 0, 0, 255])));
+        assert_eq!(parse_color("White"), Some(Rgba([255, 255, 255, 255])));
+        assert_eq!(parse_color("reddish"), None);
+        assert_eq!(parse_color("#+f8"), None);
+    }
+
+    #[test]
+    fn test_content_rect() {
+        let white = Rgba([255, 255, 255, 255]);
+        let red = Rgba([255, 0, 0, 255]);
+
+        // 10x8 white image with red content at x 2..5, y 3..7
+        let content = Rect {
+            x: 2,
+            y: 3,
+            w: 3,
+            h: 4,
+        };
+        let mut image = RgbaImage::from_pixel(10, 8, white);
+        for y in 3..7 {
+            for x in 2..5 {
+                image.put_pixel(x, y, red);
+            }
+        }
+        assert_eq!(content_rect(&image), Some(content));
+
+        // Content touching an edge, the border is still the most common color
+        image.put_pixel(0, 4, red);
+        assert_eq!(
+            content_rect(&image),
+            Some(Rect {
+                x: 0,
+                w: 5,
+                ..content
+            })
+        );
+
+        // No border at all
+        let image = RgbaImage::from_fn(3, 3, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        assert_eq!(
+            content_rect(&image),
+            Some(Rect {
+                x: 0,
+                y: 0,
+                w: 3,
+                h: 3
+            })
+        );
+
+        // Nothing but border
+        assert_eq!(content_rect(&RgbaImage::from_pixel(4, 4, white)), None);
+    }
+
+    #[test]
+    fn test_is_black() {
+        let mut image = RgbaImage::from_pixel(64, 64, Rgba([0, 4, 8, 255]));
+        assert!(is_black(&image));
+
+        image.put_pixel(32, 32, Rgba([200, 200, 200, 255]));
+        assert!(!is_black(&image));
+
+        // Transparent pixels are not considered
+        assert!(is_black(&RgbaImage::from_pixel(
+            64,
+            64,
+            Rgba([255, 255, 255, 0])
+        )));
+    }
+
+    #[test]
+    fn test_diff_image() {
+        let a = RgbaImage::from_pixel(4, 2, Rgba([0, 0, 0, 255]));
+        let mut b = a.clone();
+        b.put_pixel(1, 1, Rgba([0, 0, 1, 255]));
+        b.put_pixel(3, 0, Rgba([0, 0, 0, 0]));
+
+        let (diff, changed) = diff_image(&a, &b).unwrap();
+        assert_eq!(changed, 2);
+        assert_eq!(diff.get_pixel(1, 1), &Rgba([255, 0, 0, 255]));
+        assert_eq!(diff.get_pixel(3, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(diff.get_pixel(0, 0), &Rgba([192, 192, 192, 255]));
+
+        assert_eq!(diff_image(&a, &a).unwrap().1, 0);
+        assert!(diff_image(&a, &RgbaImage::new(2, 4)).is_none());
+    }
+
+    #[test]
+    fn test_differing_fraction() {
+        let a = RgbaImage::from_pixel(16, 16, Rgba([0, 0, 0, 255]));
+        let mut b = a.clone();
+        assert_eq!(differing_fraction(&a, &b), 0.0);
+
+        // Tear the bottom half
+        for y in 8..16 {
+            for x in 0..16 {
+                b.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+            }
+        }
+        assert_eq!(differing_fraction(&a, &b), 0.5);
+
+        assert_eq!(differing_fraction(&a, &RgbaImage::new(8, 8)), 1.0);
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        // Test vectors from RFC 4648
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+        assert_eq!(base64_encode(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        for len in 0..data.len() {
+            assert_eq!(base64_decode(&base64_encode(&data[..len])), &data[..len]);
+        }
+    }
+}
+
 mod parse_geometry {
     use crate::util;
 
@@ -97,24 +1163,32 @@ mod parse_geometry {
         comb::map(chr::one_of("-+"), |s| if s == '-' { -1 } else { 1 })(i)
     }
 
-    fn signed_integer(i: &str) -> nom::IResult<&str, i32> {
+    /// A value in pixels, or in percent if followed by `%`.
+    fn component(i: &str) -> nom::IResult<&str, (i32, bool)> {
+        seq::pair(
+            integer,
+            comb::map(comb::opt(chr::char('%')), |p| p.is_some()),
+        )(i)
+    }
+
+    fn signed_component(i: &str) -> nom::IResult<&str, (i32, bool)> {
         comb::map(
-            seq::pair(comb::map(comb::opt(sign), |s| s.unwrap_or(1)), integer),
-            |(s, m)| s * m,
+            seq::pair(comb::map(comb::opt(sign), |s| s.unwrap_or(1)), component),
+            |(s, (m, percent))| (s * m, percent),
         )(i)
     }
 
-    /// Parse a string of the form `=<width>x<height>{+-}<xoffset>{+-}<yoffset>` into a [`util::Rect`].
-    pub fn parse_geometry(g: &str) -> Option<util::Rect> {
-        let (remainder, (_, w, _, h, sx, x, sy, y)) = seq::tuple((
+    /// Width, height, x and y offsets, each with whether it is a percentage.
+    fn parse_components(g: &str) -> Option<[(i32, bool); 4]> {
+        let (remainder, (_, w, _, h, sx, (x, px), sy, (y, py))) = seq::tuple((
             comb::opt(equal_sign),
-            integer,
+            component,
             x_sign,
-            integer,
+            component,
             sign,
-            signed_integer,
+            signed_component,
             sign,
-            signed_integer,
+            signed_component,
         ))(g)
         .ok()?;
 
@@ -122,11 +1196,66 @@ mod parse_geometry {
             return None;
         }
 
+        Some([w, h, (sx * x, px), (sy * y, py)])
+    }
+
+    /// Parse a string of the form `=<width>x<height>{+-}<xoffset>{+-}<yoffset>` into a [`util::Rect`].
+    pub fn parse_geometry(g: &str) -> Option<util::Rect> {
+        match parse_components(g)? {
+            [(w, false), (h, false), (x, false), (y, false)] => Some(util::Rect { w, h, x, y }),
+            _ => None,
+        }
+    }
+
+    /// Same as [`parse_geometry`], but any of the values may also be a percentage of the size of
+    /// `bounds`, e.g. `50%x50%+25%+25%` for its centered quarter. Offsets in percent are from the
+    /// corner of `bounds`, offsets in pixels stay absolute.
+    pub fn parse_relative_geometry(g: &str, bounds: util::Rect) -> Option<util::Rect> {
+        let [w, h, x, y] = parse_components(g)?;
+        let resolve = |(value, percent), size: i32| {
+            if percent {
+                (size as i64 * value as i64 / 100) as i32
+            } else {
+                value
+            }
+        };
+        let offset = |(value, percent), start: i32, size: i32| {
+            if percent {
+                start + resolve((value, percent), size)
+            } else {
+                value
+            }
+        };
+
         Some(util::Rect {
-            w,
-            h,
-            x: sx * x,
-            y: sy * y,
+            w: resolve(w, bounds.w),
+            h: resolve(h, bounds.h),
+            x: offset(x, bounds.x, bounds.w),
+            y: offset(y, bounds.y, bounds.h),
+        })
+    }
+
+    /// Parse a position of the form `<x>,<y>`, where either coordinate may be a percentage of the
+    /// size of `bounds`, measured from its corner like in [`parse_relative_geometry`].
+    pub fn parse_relative_point(p: &str, bounds: util::Rect) -> Option<util::Point> {
+        let (remainder, ((x, px), _, (y, py))) =
+            seq::tuple((signed_component, chr::char(','), signed_component))(p).ok()?;
+
+        if !remainder.is_empty() {
+            return None;
+        }
+
+        let resolve = |value: i32, percent, start: i32, size: i32| {
+            if percent {
+                start + (size as i64 * value as i64 / 100) as i32
+            } else {
+                value
+            }
+        };
+
+        Some(util::Point {
+            x: resolve(x, px, bounds.x, bounds.w),
+            y: resolve(y, py, bounds.y, bounds.h),
         })
     }
 
@@ -148,8 +1277,60 @@ mod parse_geometry {
             // Double signs
             // https://github.com/neXromancers/shotgun/issues/41
             assert_eq!(parse_geometry("80x24++300+-49"), res);
+
+            // Percentages need something to be relative to
+            assert_eq!(parse_geometry("50%x24+300-49"), None);
+        }
+
+        #[test]
+        fn test_parse_relative_geometry() {
+            let rect = |x, y, w, h| util::Rect { x, y, w, h };
+            let screen = rect(0, 0, 1920, 1080);
+            assert_eq!(
+                parse_relative_geometry("50%x50%+25%+25%", screen),
+                Some(rect(480, 270, 960, 540))
+            );
+            assert_eq!(
+                parse_relative_geometry("100%x200+0+0", screen),
+                Some(rect(0, 0, 1920, 200))
+            );
+            assert_eq!(
+                parse_relative_geometry("80x24+300-49", screen),
+                Some(rect(300, -49, 80, 24))
+            );
+
+            // Offsets in percent start from the window, those in pixels don't
+            let window = rect(100, 50, 800, 600);
+            assert_eq!(
+                parse_relative_geometry("10%x10%+50%+10", window),
+                Some(rect(500, 10, 80, 60))
+            );
+            assert_eq!(
+                parse_relative_geometry("10%x10%+-10%+10", window),
+                Some(rect(20, 10, 80, 60))
+            );
+
+            assert_eq!(parse_relative_geometry("50%%x50+0+0", screen), None);
+        }
+
+        #[test]
+        fn test_parse_relative_point() {
+            let point = |x, y| Some(util::Point { x, y });
+            let window = util::Rect {
+                x: 100,
+                y: 50,
+                w: 800,
+                h: 600,
+            };
+            assert_eq!(parse_relative_point("10,-20", window), point(10, -20));
+            assert_eq!(parse_relative_point("50%,50%", window), point(500, 350));
+            assert_eq!(parse_relative_point("50%,20", window), point(500, 20));
+            assert_eq!(parse_relative_point("10", window), None);
+            assert_eq!(parse_relative_point("10,20,", window), None);
         }
     }
 }
 
 pub use parse_geometry::parse_geometry;
+pub use parse_geometry::parse_relative_geometry;
+pub use parse_geometry::parse_relative_point;
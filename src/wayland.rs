@@ -0,0 +1,698 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Capture backend for wlroots-based Wayland compositors, through wlr-screencopy.
+//!
+//! Only a handful of requests and events are needed, so this speaks the Wayland wire protocol
+//! directly instead of pulling in a client library.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use image::imageops;
+use image::RgbaImage;
+
+use crate::fbdev;
+use crate::fbdev::Bitfield;
+use crate::fbdev::PixelFormat;
+
+/// The wl_display singleton always has object id 1.
+const DISPLAY: u32 = 1;
+
+// wl_shm formats, the first two are special-cased, the others are DRM fourcc codes
+const ARGB8888: u32 = 0;
+const XRGB8888: u32 = 1;
+const ABGR8888: u32 = 0x3432_4241;
+const XBGR8888: u32 = 0x3432_4258;
+const ARGB2101010: u32 = 0x3033_5241;
+const XRGB2101010: u32 = 0x3033_5258;
+const ABGR2101010: u32 = 0x3033_4241;
+const XBGR2101010: u32 = 0x3033_4258;
+
+/// zwlr_screencopy_frame_v1 flag for buffers stored bottom row first.
+const Y_INVERT: u32 = 1;
+
+/// Builder for the arguments of a request.
+#[derive(Default)]
+struct Args(Vec<u8>);
+
+impl Args {
+    fn uint(mut self, v: u32) -> Self {
+        self.0.extend_from_slice(&v.to_ne_bytes());
+        self
+    }
+
+    fn int(self, v: i32) -> Self {
+        self.uint(v as u32)
+    }
+
+    /// Strings are sent with their length, including a NUL terminator, padded to 32 bits.
+    fn string(mut self, s: &str) -> Self {
+        self = self.uint(s.len() as u32 + 1);
+        self.0.extend_from_slice(s.as_bytes());
+        let padded = (s.len() + 1).div_ceil(4) * 4;
+        self.0.resize(self.0.len() + padded - s.len(), 0);
+        self
+    }
+}
+
+/// Reader for the arguments of an event.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn uint(&mut self) -> io::Result<u32> {
+        if self.0.len() < 4 {
+            return Err(invalid_data("truncated event"));
+        }
+        let (v, rest) = self.0.split_at(4);
+        self.0 = rest;
+        Ok(u32::from_ne_bytes([v[0], v[1], v[2], v[3]]))
+    }
+
+    fn int(&mut self) -> io::Result<i32> {
+        self.uint().map(|v| v as i32)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.uint()? as usize;
+        let padded = len.div_ceil(4) * 4;
+        if self.0.len() < padded {
+            return Err(invalid_data("truncated event"));
+        }
+        let (s, rest) = self.0.split_at(padded);
+        self.0 = rest;
+        // Null strings have a length of zero, others end with a NUL
+        let s = &s[..len.saturating_sub(1)];
+        Ok(String::from_utf8_lossy(s).into_owned())
+    }
+}
+
+struct Event {
+    object: u32,
+    opcode: u16,
+    args: Vec<u8>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Frame a request for `object`, the header holds the total size and the opcode.
+fn encode_request(object: u32, opcode: u16, args: &Args) -> Vec<u8> {
+    let size = 8 + args.0.len() as u32;
+    let mut message = Vec::with_capacity(size as usize);
+    message.extend_from_slice(&object.to_ne_bytes());
+    message.extend_from_slice(&(size << 16 | opcode as u32).to_ne_bytes());
+    message.extend_from_slice(&args.0);
+    message
+}
+
+/// Path of the compositor socket, `$WAYLAND_DISPLAY` is relative to `$XDG_RUNTIME_DIR`.
+fn socket_path() -> io::Result<PathBuf> {
+    let name = env::var_os("WAYLAND_DISPLAY").unwrap_or_else(|| "wayland-0".into());
+    let name = PathBuf::from(name);
+    if name.is_absolute() {
+        return Ok(name);
+    }
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Ok(PathBuf::from(dir).join(name)),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "$XDG_RUNTIME_DIR is not set",
+        )),
+    }
+}
+
+struct Connection {
+    stream: UnixStream,
+    buf: Vec<u8>,
+    next_id: u32,
+}
+
+impl Connection {
+    fn connect() -> io::Result<Connection> {
+        Ok(Connection {
+            stream: UnixStream::connect(socket_path()?)?,
+            buf: Vec::new(),
+            next_id: DISPLAY + 1,
+        })
+    }
+
+    fn new_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn send(&mut self, object: u32, opcode: u16, args: Args) -> io::Result<()> {
+        self.stream
+            .write_all(&encode_request(object, opcode, &args))
+    }
+
+    /// Send a request along with a file descriptor, which travels out of band.
+    fn send_with_fd(&mut self, object: u32, opcode: u16, args: Args, fd: RawFd) -> io::Result<()> {
+        let mut message = encode_request(object, opcode, &args);
+        let mut iov = libc::iovec {
+            iov_base: message.as_mut_ptr() as *mut libc::c_void,
+            iov_len: message.len(),
+        };
+
+        // SAFETY: the control buffer is sized with CMSG_SPACE for the one descriptor it holds,
+        // and every pointer handed to sendmsg outlives the call
+        let sent = unsafe {
+            let space = libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as usize;
+            let mut control = vec![0u8; space];
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = space as _;
+
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+
+            libc::sendmsg(self.stream.as_raw_fd(), &msg, 0)
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if sent as usize != message.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "short write to the compositor",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Wait for the next event, turning protocol errors into I/O errors.
+    fn recv(&mut self) -> io::Result<Event> {
+        loop {
+            if self.buf.len() >= 8 {
+                let header =
+                    u32::from_ne_bytes([self.buf[4], self.buf[5], self.buf[6], self.buf[7]]);
+                let size = (header >> 16) as usize;
+                if size < 8 {
+                    return Err(invalid_data("malformed event"));
+                }
+                if self.buf.len() >= size {
+                    let object =
+                        u32::from_ne_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]);
+                    let event = Event {
+                        object,
+                        opcode: header as u16,
+                        args: self.buf[8..size].to_vec(),
+                    };
+                    self.buf.drain(..size);
+
+                    // wl_display.error, the connection is dead after this
+                    if event.object == DISPLAY && event.opcode == 0 {
+                        let mut args = Reader(&event.args);
+                        let (object, code) = (args.uint()?, args.uint()?);
+                        let message = args.string()?;
+                        return Err(io::Error::other(format!(
+                            "protocol error {code} on object {object}: {message}"
+                        )));
+                    }
+                    return Ok(event);
+                }
+            }
+
+            let mut chunk = [0; 4096];
+            let n = self.stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the compositor closed the connection",
+                ));
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Hand every event to `handle` until the compositor has processed all previous requests.
+    fn roundtrip<F>(&mut self, mut handle: F) -> io::Result<()>
+    where
+        F: FnMut(&Event) -> io::Result<()>,
+    {
+        let callback = self.new_id();
+        // wl_display.sync
+        self.send(DISPLAY, 0, Args::default().uint(callback))?;
+        loop {
+            let event = self.recv()?;
+            if event.object == callback {
+                return Ok(());
+            }
+            handle(&event)?;
+        }
+    }
+
+    /// Bind a global from the registry, new ids without a fixed interface spell it out.
+    fn bind(&mut self, registry: u32, name: u32, interface: &str, version: u32) -> io::Result<u32> {
+        let id = self.new_id();
+        let args = Args::default()
+            .uint(name)
+            .string(interface)
+            .uint(version)
+            .uint(id);
+        self.send(registry, 0, args)?;
+        Ok(id)
+    }
+}
+
+/// Where an output sits in the compositor space, and how its buffer is turned.
+#[derive(Copy, Clone)]
+struct Output {
+    x: i32,
+    y: i32,
+    transform: i32,
+    /// Integer scale from wl_output, only used without xdg-output
+    scale: i32,
+    /// Size in the compositor space, from xdg-output
+    logical_size: Option<(i32, i32)>,
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output {
+            x: 0,
+            y: 0,
+            transform: 0,
+            scale: 1,
+            logical_size: None,
+        }
+    }
+}
+
+/// Place captures of `size` pixels at the physical position of their output, relative to the
+/// top-left corner of them all.
+///
+/// Output positions are in the compositor space, so this only works if every output has the
+/// same ratio of pixels to compositor units.
+fn layout(captures: &[(Output, (u32, u32))]) -> io::Result<Vec<(i64, i64)>> {
+    let ratios: Vec<f64> = captures
+        .iter()
+        .map(|(output, (w, _))| {
+            let logical_w = match output.logical_size {
+                Some((lw, _)) => lw as f64,
+                None => *w as f64 / output.scale.max(1) as f64,
+            };
+            *w as f64 / logical_w.max(1.0)
+        })
+        .collect();
+    let ratio = ratios.first().copied().unwrap_or(1.0);
+    if ratios.iter().any(|r| (r - ratio).abs() > 0.01) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the outputs have different scales, they can't be laid out in one image",
+        ));
+    }
+
+    let left = captures.iter().map(|(o, _)| o.x).min().unwrap_or(0);
+    let top = captures.iter().map(|(o, _)| o.y).min().unwrap_or(0);
+    Ok(captures
+        .iter()
+        .map(|(o, _)| {
+            (
+                ((o.x - left) as f64 * ratio).round() as i64,
+                ((o.y - top) as f64 * ratio).round() as i64,
+            )
+        })
+        .collect())
+}
+
+/// Layout of a wl_shm format, all of them are little-endian.
+fn pixel_format(format: u32) -> Option<PixelFormat> {
+    let field = |offset, length| Bitfield {
+        offset,
+        length,
+        msb_right: 0,
+    };
+    // The alpha of outputs is meaningless, screens are opaque
+    let (red, green, blue) = match format {
+        ARGB8888 | XRGB8888 => (field(16, 8), field(8, 8), field(0, 8)),
+        ABGR8888 | XBGR8888 => (field(0, 8), field(8, 8), field(16, 8)),
+        ARGB2101010 | XRGB2101010 => (field(20, 10), field(10, 10), field(0, 10)),
+        ABGR2101010 | XBGR2101010 => (field(0, 10), field(10, 10), field(20, 10)),
+        _ => return None,
+    };
+    Some(PixelFormat {
+        bits_per_pixel: 32,
+        red,
+        green,
+        blue,
+        transp: Bitfield::default(),
+    })
+}
+
+/// Undo the output transform applied to a captured buffer, to get the picture as displayed.
+fn untransform(image: RgbaImage, transform: i32, y_invert: bool) -> RgbaImage {
+    let image = if y_invert {
+        imageops::flip_vertical(&image)
+    } else {
+        image
+    };
+    // The compositor turned the picture counter-clockwise, after flipping it for 4 and up
+    let image = match transform & 3 {
+        1 => imageops::rotate90(&image),
+        2 => imageops::rotate180(&image),
+        3 => imageops::rotate270(&image),
+        _ => image,
+    };
+    if transform & 4 != 0 {
+        imageops::flip_horizontal(&image)
+    } else {
+        image
+    }
+}
+
+/// Capture one output as displayed, with the pointer drawn in if `cursor` is set.
+fn capture_output(
+    conn: &mut Connection,
+    manager: u32,
+    shm: u32,
+    output: u32,
+    transform: i32,
+    cursor: bool,
+) -> io::Result<RgbaImage> {
+    let frame = conn.new_id();
+    // zwlr_screencopy_manager_v1.capture_output
+    let args = Args::default().uint(frame).int(cursor as i32).uint(output);
+    conn.send(manager, 0, args)?;
+
+    let failed = || io::Error::other("the compositor failed to copy the output");
+
+    // zwlr_screencopy_frame_v1.buffer describes the buffer we have to provide
+    let (format, width, height, stride) = loop {
+        let event = conn.recv()?;
+        if event.object != frame {
+            continue;
+        }
+        match event.opcode {
+            0 => {
+                let mut args = Reader(&event.args);
+                break (args.uint()?, args.uint()?, args.uint()?, args.uint()?);
+            }
+            3 => return Err(failed()),
+            _ => {}
+        }
+    };
+    let layout = pixel_format(format).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("unsupported pixel format {format:#x}"),
+        )
+    })?;
+    let size = stride as usize * height as usize;
+
+    // SAFETY: the name is a valid C string, the returned descriptor is owned by the File
+    let fd = unsafe { libc::memfd_create(b"shotgun\0".as_ptr() as *const libc::c_char, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.set_len(size as u64)?;
+
+    let pool = conn.new_id();
+    // wl_shm.create_pool
+    let args = Args::default().uint(pool).int(size as i32);
+    conn.send_with_fd(shm, 0, args, file.as_raw_fd())?;
+    let buffer = conn.new_id();
+    // wl_shm_pool.create_buffer
+    let args = Args::default()
+        .uint(buffer)
+        .int(0)
+        .int(width as i32)
+        .int(height as i32)
+        .int(stride as i32)
+        .uint(format);
+    conn.send(pool, 0, args)?;
+    // zwlr_screencopy_frame_v1.copy
+    conn.send(frame, 0, Args::default().uint(buffer))?;
+
+    let mut flags = 0;
+    let result = loop {
+        let event = conn.recv()?;
+        if event.object != frame {
+            continue;
+        }
+        match event.opcode {
+            1 => flags = Reader(&event.args).uint()?,
+            2 => break Ok(()),
+            3 => break Err(failed()),
+            _ => {}
+        }
+    };
+
+    // wl_buffer.destroy, wl_shm_pool.destroy and zwlr_screencopy_frame_v1.destroy
+    conn.send(buffer, 0, Args::default())?;
+    conn.send(pool, 1, Args::default())?;
+    conn.send(frame, 1, Args::default())?;
+    result?;
+
+    let mut data = Vec::with_capacity(size);
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut data)?;
+    let image = fbdev::convert(&data, width, height, stride as usize, layout)
+        .ok_or_else(|| invalid_data("unsupported pixel format"))?;
+    Ok(untransform(image, transform, flags & Y_INVERT != 0))
+}
+
+/// Capture every output of a wlroots-based compositor, laid out as they are on screen.
+pub fn capture(cursor: bool) -> io::Result<RgbaImage> {
+    let mut conn = Connection::connect()?;
+
+    let registry = conn.new_id();
+    // wl_display.get_registry
+    conn.send(DISPLAY, 1, Args::default().uint(registry))?;
+    let mut globals = Vec::new();
+    conn.roundtrip(|event| {
+        // wl_registry.global
+        if event.object == registry && event.opcode == 0 {
+            let mut args = Reader(&event.args);
+            globals.push((args.uint()?, args.string()?, args.uint()?));
+        }
+        Ok(())
+    })?;
+
+    let find = |interface: &str| {
+        globals
+            .iter()
+            .find(|(_, i, _)| i == interface)
+            .map(|(name, _, _)| *name)
+    };
+    let manager_name = find("zwlr_screencopy_manager_v1").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the compositor doesn't support wlr-screencopy",
+        )
+    })?;
+    let shm_name = find("wl_shm").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the compositor doesn't support wl_shm",
+        )
+    })?;
+    let manager = conn.bind(registry, manager_name, "zwlr_screencopy_manager_v1", 1)?;
+    let shm = conn.bind(registry, shm_name, "wl_shm", 1)?;
+
+    let mut outputs = HashMap::new();
+    let mut order = Vec::new();
+    for (name, interface, version) in &globals {
+        if interface == "wl_output" {
+            // Version 2 adds the scale
+            let id = conn.bind(registry, *name, "wl_output", (*version).min(2))?;
+            outputs.insert(id, Output::default());
+            order.push(id);
+        }
+    }
+    if order.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no outputs to capture",
+        ));
+    }
+
+    // xdg-output gives the size of outputs in the compositor space, fractional scales included
+    let mut xdg_outputs = HashMap::new();
+    if let Some(name) = find("zxdg_output_manager_v1") {
+        let xdg_manager = conn.bind(registry, name, "zxdg_output_manager_v1", 1)?;
+        for &id in &order {
+            let xdg_output = conn.new_id();
+            // zxdg_output_manager_v1.get_xdg_output
+            conn.send(xdg_manager, 1, Args::default().uint(xdg_output).uint(id))?;
+            xdg_outputs.insert(xdg_output, id);
+        }
+    }
+
+    conn.roundtrip(|event| {
+        if let Some(output) = outputs.get_mut(&event.object) {
+            let mut args = Reader(&event.args);
+            match event.opcode {
+                // wl_output.geometry
+                0 => {
+                    let (x, y) = (args.int()?, args.int()?);
+                    // xdg-output has the final say on positions
+                    if output.logical_size.is_none() {
+                        output.x = x;
+                        output.y = y;
+                    }
+                    // Skip the physical size, subpixel layout, make and model
+                    args.int()?;
+                    args.int()?;
+                    args.int()?;
+                    args.string()?;
+                    args.string()?;
+                    output.transform = args.int()?;
+                }
+                // wl_output.scale
+                3 => output.scale = args.int()?,
+                _ => {}
+            }
+        } else if let Some(id) = xdg_outputs.get(&event.object) {
+            let output = outputs.get_mut(id).unwrap();
+            let mut args = Reader(&event.args);
+            match event.opcode {
+                // zxdg_output_v1.logical_position
+                0 => {
+                    output.x = args.int()?;
+                    output.y = args.int()?;
+                    output.logical_size.get_or_insert((0, 0));
+                }
+                // zxdg_output_v1.logical_size
+                1 => output.logical_size = Some((args.int()?, args.int()?)),
+                _ => {}
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut captures = Vec::new();
+    for id in order {
+        let output = outputs[&id];
+        let image = capture_output(&mut conn, manager, shm, id, output.transform, cursor)?;
+        captures.push((output, image));
+    }
+
+    // Compose the outputs over their bounding box
+    let sizes: Vec<_> = captures.iter().map(|(o, i)| (*o, i.dimensions())).collect();
+    let positions = layout(&sizes)?;
+    let bounds = |axis: fn((i64, i64), (u32, u32)) -> i64| {
+        positions
+            .iter()
+            .zip(&sizes)
+            .map(|(&p, &(_, size))| axis(p, size))
+            .max()
+            .unwrap_or(0)
+    };
+    let width = bounds(|(x, _), (w, _)| x + w as i64);
+    let height = bounds(|(_, y), (_, h)| y + h as i64);
+    let mut image = RgbaImage::new(width as u32, height as u32);
+    for ((x, y), (_, capture)) in positions.into_iter().zip(&captures) {
+        imageops::replace(&mut image, capture, x, y);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use image::Rgba;
+
+    #[test]
+    fn test_encode_request() {
+        let message = encode_request(3, 2, &Args::default().uint(7).string("wl_shm"));
+        // Header, uint, string length and "wl_shm\0" padded to 8 bytes
+        assert_eq!(message.len(), 8 + 4 + 4 + 8);
+        assert_eq!(&message[..4], &3u32.to_ne_bytes());
+        assert_eq!(&message[4..8], &(24u32 << 16 | 2).to_ne_bytes());
+        assert_eq!(&message[12..16], &7u32.to_ne_bytes());
+        assert_eq!(&message[16..24], b"wl_shm\0\0");
+    }
+
+    #[test]
+    fn test_reader() {
+        let args = Args::default().int(-5).string("wl_output").string("");
+        let mut reader = Reader(&args.0);
+        assert_eq!(reader.int().unwrap(), -5);
+        assert_eq!(reader.string().unwrap(), "wl_output");
+        assert_eq!(reader.string().unwrap(), "");
+        assert!(reader.uint().is_err());
+    }
+
+    #[test]
+    fn test_pixel_format() {
+        let convert = |format, pixel: u32| {
+            let layout = pixel_format(format).unwrap();
+            *fbdev::convert(&pixel.to_le_bytes(), 1, 1, 4, layout)
+                .unwrap()
+                .get_pixel(0, 0)
+        };
+        assert_eq!(
+            convert(XRGB8888, 0x0011_2233),
+            Rgba([0x11, 0x22, 0x33, 255])
+        );
+        assert_eq!(
+            convert(ARGB8888, 0x8011_2233),
+            Rgba([0x11, 0x22, 0x33, 255])
+        );
+        assert_eq!(
+            convert(XBGR8888, 0x0011_2233),
+            Rgba([0x33, 0x22, 0x11, 255])
+        );
+        assert_eq!(
+            convert(XRGB2101010, 0x3ff << 20 | 0x200 << 10),
+            Rgba([255, 128, 0, 255])
+        );
+        assert!(pixel_format(0x3231_5258).is_none());
+    }
+
+    #[test]
+    fn test_layout() {
+        let output = |x, scale, logical_size| Output {
+            x,
+            scale,
+            logical_size,
+            ..Output::default()
+        };
+        // A 2x panel is 1280 units wide, the monitor on its right starts there
+        let outputs = [
+            (output(0, 2, None), (2560, 1600)),
+            (output(1280, 2, None), (3840, 2160)),
+        ];
+        assert_eq!(layout(&outputs).unwrap(), vec![(0, 0), (2560, 0)]);
+
+        let outputs = [
+            (output(0, 1, Some((1706, 1067))), (2560, 1600)),
+            (output(1706, 1, Some((1920, 1080))), (1920, 1080)),
+        ];
+        assert!(layout(&outputs).is_err());
+    }
+
+    #[test]
+    fn test_untransform() {
+        let image = RgbaImage::from_fn(2, 1, |x, _| Rgba([x as u8, 0, 0, 255]));
+        assert_eq!(untransform(image.clone(), 0, false), image);
+        let turned = untransform(image.clone(), 1, false);
+        assert_eq!(turned.dimensions(), (1, 2));
+        assert_eq!(turned.get_pixel(0, 0)[0], 0);
+        let flipped = untransform(image, 4, true);
+        assert_eq!(flipped.get_pixel(0, 0)[0], 1);
+    }
+}
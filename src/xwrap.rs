@@ -2,18 +2,76 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::thread;
+use std::time;
+
 use image::Rgba;
 use image::RgbaImage;
-use x11rb::connection::Connection;
-use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::connection::{Connection, RequestConnection as _};
+use x11rb::protocol::present::{self, ConnectionExt as _};
+use x11rb::protocol::randr::{self, ConnectionExt as _};
+use x11rb::protocol::render::{self, ConnectionExt as _};
+use x11rb::protocol::shm::{self, ConnectionExt as _};
+use x11rb::protocol::xfixes::ConnectionExt as _;
+use x11rb::protocol::xinerama::{self, ConnectionExt as _};
 use x11rb::protocol::xproto::{self, ConnectionExt as _};
 use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
 
 use crate::util;
 
+/// How often to check for clipboard requests when serving the clipboard with a timeout.
+const CLIPBOARD_POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+/// How often to check whether a vertical blank was notified.
+const VBLANK_POLL_INTERVAL: time::Duration = time::Duration::from_millis(1);
+/// Size of the pieces of clipboard data too large to be sent at once.
+const CLIPBOARD_CHUNK_SIZE: usize = 256 * 1024;
+
 pub struct Display {
     conn: RustConnection,
     screen: usize,
+    /// Capture through RENDER instead of plain GetImage, see [`Display::enable_render`]
+    use_render: bool,
+    /// See [`Display::force_channel_order`]
+    channel_order: Option<ChannelOrder>,
+    /// Whether GetImage can go through MIT-SHM, cleared the first time it fails (e.g. because the
+    /// server is on another host)
+    use_shm: Cell<bool>,
+}
+
+/// Order of the bytes of a 32-bit pixel in memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelOrder {
+    Rgba,
+    Bgra,
+}
+
+/// A screen along with what RandR knows about it.
+pub struct Screen {
+    pub rect: util::Rect,
+    /// Name of the output (connector) showing this screen, e.g. `DP-1`
+    pub name: Option<String>,
+    /// Rotation in degrees, counter-clockwise
+    pub rotation: u16,
+    /// Refresh rate in Hz, if known
+    pub refresh_rate: Option<f64>,
+    /// Width and height in millimetres as shown, if the monitor reports them
+    pub physical_size: Option<(u32, u32)>,
+}
+
+/// Layout of the pixels returned by [`Image::into_raw`]: `height` rows of `width` pixels of 4
+/// bytes each, without padding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RawFormat {
+    pub width: u32,
+    pub height: u32,
+    pub order: ChannelOrder,
+    /// Whether the fourth byte is alpha, otherwise it is undefined and pixels are opaque
+    pub alpha: bool,
+    /// Whether colors are premultiplied by alpha, as in ARGB visuals
+    pub premultiplied: bool,
 }
 
 pub struct Image {
@@ -25,10 +83,139 @@ pub struct Image {
     data: Vec<u8>,
 }
 
+/// Why an MIT-SHM capture failed.
+#[cfg(unix)]
+enum ShmError {
+    /// No segment could be created or attached by the server
+    Unavailable,
+    /// The GetImage request itself failed, plain GetImage may still work
+    Request,
+}
+
+/// A System V shared memory segment, attached to both shotgun and the X server.
+#[cfg(unix)]
+struct ShmSegment<'a> {
+    conn: &'a RustConnection,
+    seg: shm::Seg,
+    id: libc::c_int,
+    addr: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(unix)]
+impl<'a> ShmSegment<'a> {
+    fn new(conn: &'a RustConnection, len: usize) -> Option<ShmSegment<'a>> {
+        // SAFETY: plain syscalls, the segment is only used through the checked pointer below
+        let id = unsafe { libc::shmget(libc::IPC_PRIVATE, len, libc::IPC_CREAT | 0o600) };
+        if id == -1 {
+            return None;
+        }
+        let addr = unsafe { libc::shmat(id, std::ptr::null(), libc::SHM_RDONLY) };
+        if addr as isize == -1 {
+            unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+            return None;
+        }
+        let mut segment = ShmSegment {
+            conn,
+            seg: x11rb::NONE,
+            id,
+            addr,
+            len,
+        };
+
+        // Remote servers can't see the segment, and answer with an Access error
+        let seg = conn.generate_id().ok()?;
+        conn.shm_attach(seg, id as u32, false).ok()?.check().ok()?;
+        segment.seg = seg;
+
+        // Both sides are attached, which keeps the segment alive until they detach. Marking it
+        // for removal now means it can't leak, even if shotgun is killed.
+        unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+        Some(segment)
+    }
+
+    fn data(&self) -> &[u8] {
+        // SAFETY: the mapping is len bytes long and lives as long as self
+        unsafe { std::slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ShmSegment<'_> {
+    fn drop(&mut self) {
+        if self.seg != x11rb::NONE {
+            // Make sure the server is done with the segment before it goes away
+            let _ = self.conn.shm_detach(self.seg).map(|c| c.check());
+        }
+        // Once attached by the server, the segment was already marked for removal. Its id may
+        // belong to another segment after shmdt, so never remove it again.
+        unsafe {
+            if self.seg == x11rb::NONE {
+                libc::shmctl(self.id, libc::IPC_RMID, std::ptr::null_mut());
+            }
+            libc::shmdt(self.addr);
+        }
+    }
+}
+
+/// A window showing a still copy of the screen, see [`Display::freeze`]. The screen comes back
+/// to life when this is dropped.
+pub struct Frozen<'a> {
+    display: &'a Display,
+    window: xproto::Window,
+    pixmap: xproto::Pixmap,
+}
+
+impl Drop for Frozen<'_> {
+    fn drop(&mut self) {
+        let conn = &self.display.conn;
+        let _ = conn.destroy_window(self.window);
+        let _ = conn.free_pixmap(self.pixmap);
+        let _ = conn.sync();
+    }
+}
+
+/// Check that `rect` is non-empty and fits the 16-bit fields of a GetImage request, so that the
+/// casts below can't silently turn it into a different (possibly huge) request.
+fn is_valid_image_rect(rect: util::Rect) -> bool {
+    rect.w > 0
+        && rect.h > 0
+        && u16::try_from(rect.w).is_ok()
+        && u16::try_from(rect.h).is_ok()
+        && i16::try_from(rect.x).is_ok()
+        && i16::try_from(rect.y).is_ok()
+}
+
 impl Display {
     pub fn open(name: Option<&str>) -> Option<Display> {
         let (conn, screen) = x11rb::connect(name).ok()?;
-        Some(Display { conn, screen })
+        let display = Display {
+            conn,
+            screen,
+            use_render: false,
+            channel_order: None,
+            use_shm: Cell::new(false),
+        };
+        display.use_shm.set(display.supports_shm());
+        Some(display)
+    }
+
+    /// Check for the MIT-SHM extension. This doesn't tell whether the server can actually attach
+    /// our segments, which only works when it runs on the same host.
+    fn supports_shm(&self) -> bool {
+        cfg!(unix)
+            && self
+                .conn
+                .extension_information(shm::X11_EXTENSION_NAME)
+                .ok()
+                .flatten()
+                .is_some()
+            && self
+                .conn
+                .shm_query_version()
+                .ok()
+                .and_then(|c| c.reply().ok())
+                .is_some()
     }
 
     fn screen(&self) -> &xproto::Screen {
@@ -81,13 +268,88 @@ impl Display {
         None
     }
 
+    /// Make GetImage captures ignore the visual and pixel format reported by the server, and
+    /// read 32-bit pixels in the given order instead. This works around drivers that report
+    /// formats which don't match the data they send.
+    pub fn force_channel_order(&mut self, order: ChannelOrder) {
+        self.channel_order = Some(order);
+    }
+
+    /// Make [`get_image`](Display::get_image) go through the RENDER extension when possible,
+    /// returning false if the server doesn't support it.
+    pub fn enable_render(&mut self) -> bool {
+        let supported = self
+            .conn
+            .extension_information(render::X11_EXTENSION_NAME)
+            .ok()
+            .flatten()
+            .is_some()
+            && self
+                .conn
+                .render_query_version(0, 11)
+                .ok()
+                .and_then(|c| c.reply().ok())
+                .is_some();
+        self.use_render = supported;
+        supported
+    }
+
     pub fn get_image(&self, window: xproto::Window, rect: util::Rect) -> Option<Image> {
+        if !is_valid_image_rect(rect) {
+            return None;
+        }
+
+        if self.use_render {
+            if let Some(image) = self.get_image_render(window, rect) {
+                return Some(image);
+            }
+        }
+        self.get_image_plain(window, rect)
+    }
+
+    fn get_image_plain(&self, window: xproto::Window, rect: util::Rect) -> Option<Image> {
+        let img = self.get_image_reply(window, rect)?;
+        if let Some(order) = self.channel_order {
+            let format = *self
+                .conn
+                .setup()
+                .pixmap_formats
+                .iter()
+                .find(|f| f.depth == img.depth)?;
+            return forced_image(rect, img, format, order);
+        }
+        // Pixmaps have no visual of their own, assume they match the root window like wallpapers
+        let visual = if img.visual == x11rb::NONE && img.depth == self.screen().root_depth {
+            *self.find_visual(self.screen().root_visual)?
+        } else {
+            *self.find_visual(img.visual)?
+        };
+        self.make_image(rect, img, visual)
+    }
+
+    fn get_image_reply(
+        &self,
+        drawable: xproto::Drawable,
+        rect: util::Rect,
+    ) -> Option<xproto::GetImageReply> {
         const ALL_PLANES: u32 = !0;
+
+        #[cfg(unix)]
+        if self.use_shm.get() {
+            match self.get_image_reply_shm(drawable, rect, ALL_PLANES) {
+                Ok(reply) => return Some(reply),
+                // The segment couldn't be shared with the server, don't bother next time
+                Err(ShmError::Unavailable) => self.use_shm.set(false),
+                // Try again without MIT-SHM, which fails too if the drawable or area is bad
+                Err(ShmError::Request) => {}
+            }
+        }
+
         let cookie = self
             .conn
             .get_image(
                 xproto::ImageFormat::Z_PIXMAP,
-                window,
+                drawable,
                 rect.x as i16,
                 rect.y as i16,
                 rect.w as u16,
@@ -95,15 +357,21 @@ impl Display {
                 ALL_PLANES,
             )
             .ok()?;
-        let img = cookie.reply().ok()?;
+        cookie.reply().ok()
+    }
 
+    fn make_image(
+        &self,
+        rect: util::Rect,
+        img: xproto::GetImageReply,
+        visual: xproto::Visualtype,
+    ) -> Option<Image> {
         let format = *self
             .conn
             .setup()
             .pixmap_formats
             .iter()
             .find(|f| f.depth == img.depth)?;
-        let visual = *self.find_visual(img.visual)?;
         let byte_order = self.conn.setup().image_byte_order;
 
         Some(Image {
@@ -116,129 +384,1623 @@ impl Display {
         })
     }
 
+    /// GetImage into a shared memory segment, which skips copying the pixels over the socket.
+    #[cfg(unix)]
+    fn get_image_reply_shm(
+        &self,
+        drawable: xproto::Drawable,
+        rect: util::Rect,
+        plane_mask: u32,
+    ) -> Result<xproto::GetImageReply, ShmError> {
+        // Enough for 32 bits per pixel, the most any depth uses, even with scanline padding
+        let len = rect.w as usize * rect.h as usize * 4;
+        let segment = ShmSegment::new(&self.conn, len).ok_or(ShmError::Unavailable)?;
+
+        let reply = self
+            .conn
+            .shm_get_image(
+                drawable,
+                rect.x as i16,
+                rect.y as i16,
+                rect.w as u16,
+                rect.h as u16,
+                plane_mask,
+                xproto::ImageFormat::Z_PIXMAP.into(),
+                segment.seg,
+                0,
+            )
+            .map_err(|_| ShmError::Request)?
+            .reply()
+            .map_err(|_| ShmError::Request)?;
+        let size = usize::try_from(reply.size).map_err(|_| ShmError::Request)?;
+        if size > len {
+            return Err(ShmError::Request);
+        }
+
+        Ok(xproto::GetImageReply {
+            depth: reply.depth,
+            sequence: reply.sequence,
+            visual: reply.visual,
+            data: segment.data()[..size].to_vec(),
+        })
+    }
+
+    /// Capture by compositing `window` into a 32-bit ARGB pixmap with RENDER, and reading that
+    /// back. Windows with an alpha channel keep it, where GetImage depends on the server's idea of
+    /// the window's visual.
+    fn get_image_render(&self, window: xproto::Window, rect: util::Rect) -> Option<Image> {
+        let attributes = self.conn.get_window_attributes(window).ok()?.reply().ok()?;
+        let formats = self.conn.render_query_pict_formats().ok()?.reply().ok()?;
+
+        let window_format = formats
+            .screens
+            .iter()
+            .flat_map(|s| &s.depths)
+            .flat_map(|d| &d.visuals)
+            .find(|v| v.visual == attributes.visual)?
+            .format;
+        let argb32 = formats
+            .formats
+            .iter()
+            .find(|f| {
+                let d = f.direct;
+                f.type_ == render::PictType::DIRECT
+                    && f.depth == 32
+                    && (d.alpha_shift, d.red_shift, d.green_shift, d.blue_shift) == (24, 16, 8, 0)
+                    && [d.alpha_mask, d.red_mask, d.green_mask, d.blue_mask] == [0xff; 4]
+            })?
+            .id;
+
+        let (w, h) = (rect.w as u16, rect.h as u16);
+        let pixmap = self.conn.generate_id().ok()?;
+        let src = self.conn.generate_id().ok()?;
+        let dst = self.conn.generate_id().ok()?;
+        self.conn
+            .create_pixmap(32, pixmap, self.root(), w, h)
+            .ok()?;
+        // Include child windows, otherwise capturing the root window gives the bare background
+        let src_aux =
+            render::CreatePictureAux::new().subwindowmode(xproto::SubwindowMode::INCLUDE_INFERIORS);
+        self.conn
+            .render_create_picture(src, window, window_format, &src_aux)
+            .ok()?;
+        self.conn
+            .render_create_picture(dst, pixmap, argb32, &Default::default())
+            .ok()?;
+        self.conn
+            .render_composite(
+                render::PictOp::SRC,
+                src,
+                x11rb::NONE,
+                dst,
+                rect.x as i16,
+                rect.y as i16,
+                0,
+                0,
+                0,
+                0,
+                w,
+                h,
+            )
+            .ok()?;
+
+        let pixmap_rect = util::Rect {
+            x: 0,
+            y: 0,
+            w: rect.w,
+            h: rect.h,
+        };
+        // Pixmaps have no visual, describe the ARGB32 layout by hand
+        let visual = xproto::Visualtype {
+            visual_id: x11rb::NONE,
+            class: xproto::VisualClass::TRUE_COLOR,
+            bits_per_rgb_value: 8,
+            colormap_entries: 256,
+            red_mask: 0xff0000,
+            green_mask: 0xff00,
+            blue_mask: 0xff,
+        };
+        let image = self
+            .get_image_reply(pixmap, pixmap_rect)
+            .and_then(|img| self.make_image(pixmap_rect, img, visual));
+
+        let _ = self.conn.render_free_picture(src);
+        let _ = self.conn.render_free_picture(dst);
+        let _ = self.conn.free_pixmap(pixmap);
+        image
+    }
+
+    /// Get the rects of all screens, from RandR or from Xinerama on servers where RandR doesn't
+    /// know about any CRTCs.
     pub fn get_screen_rects(&self) -> Option<Vec<util::Rect>> {
+        match self.get_randr_screen_rects() {
+            Some(rects) if !rects.is_empty() => Some(rects),
+            _ => self.get_xinerama_screen_rects(),
+        }
+    }
+
+    pub fn get_xinerama_screen_rects(&self) -> Option<Vec<util::Rect>> {
+        // Don't send requests for an extension the server doesn't have
+        self.conn
+            .extension_information(xinerama::X11_EXTENSION_NAME)
+            .ok()??;
+
+        let cookie = self.conn.xinerama_is_active().ok()?;
+        if cookie.reply().ok()?.state == 0 {
+            return None;
+        }
+
+        let cookie = self.conn.xinerama_query_screens().ok()?;
+        let screens = cookie.reply().ok()?;
+
+        let rects = screens
+            .screen_info
+            .iter()
+            .map(|info| util::Rect {
+                x: info.x_org as i32,
+                y: info.y_org as i32,
+                w: info.width as i32,
+                h: info.height as i32,
+            })
+            .collect();
+        Some(rects)
+    }
+
+    fn get_randr_screen_rects(&self) -> Option<Vec<util::Rect>> {
         let cookie = self
             .conn
             .randr_get_screen_resources_current(self.root())
             .ok()?;
         let res = cookie.reply().ok()?;
 
-        let rects = res
-            .crtcs
+        // A CRTC can go away between the two requests when a monitor is unplugged, which is
+        // an X error rather than a reason to panic
+        res.crtcs
             .iter()
             .map(|&crtc| {
                 let cookie = self
                     .conn
                     .randr_get_crtc_info(crtc, res.config_timestamp)
-                    .expect("Invalid CRTC info request");
-                let info = cookie.reply().expect("Failed to get CRTC info");
-                util::Rect {
+                    .ok()?;
+                let info = cookie.reply().ok()?;
+                Some(util::Rect {
                     x: info.x as i32,
                     y: info.y as i32,
                     w: info.width as i32,
                     h: info.height as i32,
-                }
+                })
             })
-            .collect::<Vec<_>>();
-        Some(rects)
+            .collect()
     }
 
-    pub fn get_cursor_position(&self) -> Option<util::Point> {
-        let cookie = self.conn.query_pointer(self.root()).ok()?;
-        let pointer = cookie.reply().ok()?;
+    /// Wait for the next vertical blank of the CRTC showing most of `window`, as notified by the
+    /// Present extension. Returns `None` if Present is missing or nothing came within `timeout`.
+    pub fn wait_for_vblank(&self, window: xproto::Window, timeout: time::Duration) -> Option<()> {
+        self.conn
+            .extension_information(present::X11_EXTENSION_NAME)
+            .ok()??;
+        self.conn.present_query_version(1, 0).ok()?.reply().ok()?;
 
-        Some(util::Point {
-            x: pointer.win_x as i32,
-            y: pointer.win_y as i32,
+        let eid = self.conn.generate_id().ok()?;
+        self.conn
+            .present_select_input(eid, window, present::EventMask::COMPLETE_NOTIFY)
+            .ok()?
+            .check()
+            .ok()?;
+        // With a target that has already passed and a divisor of 1, this is the next frame
+        let serial = eid;
+        let notified = self
+            .conn
+            .present_notify_msc(window, serial, 0, 1, 0)
+            .and_then(|_| self.conn.flush())
+            .is_ok();
+
+        let deadline = time::Instant::now() + timeout;
+        let result = loop {
+            if !notified {
+                break None;
+            }
+            match self.conn.poll_for_event() {
+                Ok(Some(x11rb::protocol::Event::PresentCompleteNotify(e)))
+                    if e.kind == present::CompleteKind::NOTIFY_MSC && e.serial == serial =>
+                {
+                    break Some(());
+                }
+                Ok(Some(_)) => {}
+                Ok(None) if time::Instant::now() < deadline => thread::sleep(VBLANK_POLL_INTERVAL),
+                _ => break None,
+            }
+        };
+
+        // An empty mask frees the event context
+        let _ = self
+            .conn
+            .present_select_input(eid, window, present::EventMask::NO_EVENT);
+        result
+    }
+
+    /// Same as [`get_screen_rects`](Display::get_screen_rects) minus disabled CRTCs, with output
+    /// names and rotations when RandR provides them.
+    pub fn get_screens(&self) -> Option<Vec<Screen>> {
+        match self.get_randr_screens() {
+            Some(screens) if !screens.is_empty() => Some(screens),
+            _ => Some(
+                self.get_xinerama_screen_rects()?
+                    .into_iter()
+                    .map(|rect| Screen {
+                        rect,
+                        name: None,
+                        rotation: 0,
+                        refresh_rate: None,
+                        physical_size: None,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn get_randr_screens(&self) -> Option<Vec<Screen>> {
+        let cookie = self
+            .conn
+            .randr_get_screen_resources_current(self.root())
+            .ok()?;
+        let res = cookie.reply().ok()?;
+
+        let mut screens = Vec::new();
+        for &crtc in &res.crtcs {
+            let info = self
+                .conn
+                .randr_get_crtc_info(crtc, res.config_timestamp)
+                .ok()?
+                .reply()
+                .ok()?;
+            // CRTCs that don't drive any output are disabled
+            let output = match info.outputs.first() {
+                Some(&o) => o,
+                None => continue,
+            };
+            let output = self
+                .conn
+                .randr_get_output_info(output, res.config_timestamp)
+                .ok()?
+                .reply()
+                .ok()?;
+            let has_rotation = |r: randr::Rotation| u16::from(info.rotation) & u16::from(r) != 0;
+            let rotation = if has_rotation(randr::Rotation::ROTATE90) {
+                90
+            } else if has_rotation(randr::Rotation::ROTATE180) {
+                180
+            } else if has_rotation(randr::Rotation::ROTATE270) {
+                270
+            } else {
+                0
+            };
+
+            screens.push(Screen {
+                rect: util::Rect {
+                    x: info.x as i32,
+                    y: info.y as i32,
+                    w: info.width as i32,
+                    h: info.height as i32,
+                },
+                name: Some(String::from_utf8_lossy(&output.name).into_owned()),
+                rotation,
+                physical_size: match (output.mm_width, output.mm_height) {
+                    (0, _) | (_, 0) => None,
+                    // The size of the panel doesn't turn with the picture
+                    (w, h) if rotation == 90 || rotation == 270 => Some((h, w)),
+                    (w, h) => Some((w, h)),
+                },
+                refresh_rate: res
+                    .modes
+                    .iter()
+                    .find(|m| m.id == info.mode)
+                    .and_then(refresh_rate),
+            });
+        }
+        Some(screens)
+    }
+
+    /// Get the rect of the screen shown on the primary RandR output, if one is set and enabled.
+    pub fn get_primary_screen_rect(&self) -> Option<util::Rect> {
+        let cookie = self.conn.randr_get_output_primary(self.root()).ok()?;
+        let primary = cookie.reply().ok()?.output;
+        if primary == x11rb::NONE {
+            return None;
+        }
+
+        let cookie = self
+            .conn
+            .randr_get_output_info(primary, x11rb::CURRENT_TIME)
+            .ok()?;
+        let output = cookie.reply().ok()?;
+        // Disabled outputs have no CRTC
+        if output.crtc == x11rb::NONE {
+            return None;
+        }
+
+        let cookie = self
+            .conn
+            .randr_get_crtc_info(output.crtc, output.timestamp)
+            .ok()?;
+        let info = cookie.reply().ok()?;
+        Some(util::Rect {
+            x: info.x as i32,
+            y: info.y as i32,
+            w: info.width as i32,
+            h: info.height as i32,
         })
     }
-}
 
-impl Image {
-    pub fn to_image_buffer(&self) -> Option<RgbaImage> {
-        if (
-            self.visual.red_mask,
-            self.visual.green_mask,
-            self.visual.blue_mask,
-        ) == (0xF800, 0x07E0, 0x001F)
-        {
-            return self.to_image_buffer_rgb565();
+    /// Get an atom, creating it if no client has used it yet.
+    fn create_atom(&self, name: &str) -> Option<xproto::Atom> {
+        let cookie = self.conn.intern_atom(false, name.as_bytes()).ok()?;
+        Some(cookie.reply().ok()?.atom)
+    }
+
+    fn intern_atom(&self, name: &str) -> Option<xproto::Atom> {
+        let cookie = self.conn.intern_atom(true, name.as_bytes()).ok()?;
+        let atom = cookie.reply().ok()?.atom;
+        if atom == x11rb::NONE {
+            None
+        } else {
+            Some(atom)
         }
+    }
 
-        let bytes_per_pixel = match (self.format.depth, self.format.bits_per_pixel) {
-            (24, bpp @ 24) | (24 | 32, bpp @ 32) => bpp as u32 / 8,
-            _ => return None,
-        };
+    /// Get the pixmap holding the desktop wallpaper, as advertised by wallpaper setters in the
+    /// `_XROOTPMAP_ID` property of the root window.
+    pub fn get_wallpaper_pixmap(&self) -> Option<xproto::Pixmap> {
+        let atom = self.intern_atom("_XROOTPMAP_ID")?;
+        let cookie = self
+            .conn
+            .get_property(false, self.root(), atom, xproto::AtomEnum::PIXMAP, 0, 1)
+            .ok()?;
+        let reply = cookie.reply().ok()?;
+        let pixmap = reply.value32()?.next();
+        pixmap.filter(|&p| p != x11rb::NONE)
+    }
 
-        let pad = match self.format.scanline_pad {
-            p @ 32 => p as u32 / 8,
-            _ => return None,
+    /// Get the EWMH work area of the current desktop, i.e. the root window minus the space
+    /// reserved by panels and docks.
+    pub fn get_work_area(&self) -> Option<util::Rect> {
+        let workarea = self.intern_atom("_NET_WORKAREA")?;
+        let desktop = match self.intern_atom("_NET_CURRENT_DESKTOP") {
+            Some(current_desktop) => {
+                let cookie = self
+                    .conn
+                    .get_property(
+                        false,
+                        self.root(),
+                        current_desktop,
+                        xproto::AtomEnum::CARDINAL,
+                        0,
+                        1,
+                    )
+                    .ok()?;
+                let reply = cookie.reply().ok()?;
+                reply.value32().and_then(|mut v| v.next()).unwrap_or(0)
+            }
+            None => 0,
         };
-        let bytes_per_line = (self.w * bytes_per_pixel + pad - 1) / pad * pad;
 
-        // Compute subpixel offsets into each pixel according the the bitmasks X gives us
-        // Only 8 bit, byte-aligned values are supported
-        // Truncate masks to the lower 32 bits as that is the maximum pixel size
-        macro_rules! channel_offset {
-            ($mask:expr) => {{
-                use xproto::ImageOrder as O;
-                match (self.byte_order, $mask & 0xFFFFFFFF) {
-                    (O::LSB_FIRST, 0xFF) | (O::MSB_FIRST, 0xFF000000) => 0,
-                    (O::LSB_FIRST, 0xFF00) | (O::MSB_FIRST, 0xFF0000) => 1,
-                    (O::LSB_FIRST, 0xFF0000) | (O::MSB_FIRST, 0xFF00) => 2,
-                    (O::LSB_FIRST, 0xFF000000) | (O::MSB_FIRST, 0xFF) => 3,
-                    _ => return None,
-                }
-            }};
+        // One x, y, width, height quadruplet per desktop
+        let cookie = self
+            .conn
+            .get_property(
+                false,
+                self.root(),
+                workarea,
+                xproto::AtomEnum::CARDINAL,
+                desktop * 4,
+                4,
+            )
+            .ok()?;
+        let reply = cookie.reply().ok()?;
+        let area: Vec<u32> = reply.value32()?.collect();
+        if area.len() < 4 {
+            return None;
         }
-        let red_offset = channel_offset!(self.visual.red_mask);
-        let green_offset = channel_offset!(self.visual.green_mask);
-        let blue_offset = channel_offset!(self.visual.blue_mask);
-        let alpha_offset = channel_offset!(
-            !(self.visual.red_mask | self.visual.green_mask | self.visual.blue_mask)
-        );
 
-        // Finally, generate the image object
-        Some(RgbaImage::from_fn(self.w, self.h, |x, y| {
-            let offset = (y * bytes_per_line + x * bytes_per_pixel) as usize;
-            Rgba([
-                self.data[offset + red_offset],
-                self.data[offset + green_offset],
-                self.data[offset + blue_offset],
-                // Make the alpha channel fully opaque if none is provided
-                if self.format.depth == 24 {
-                    0xFF
-                } else {
-                    self.data[offset + alpha_offset]
-                },
-            ])
-        }))
+        Some(util::Rect {
+            x: area[0] as i32,
+            y: area[1] as i32,
+            w: area[2] as i32,
+            h: area[3] as i32,
+        })
     }
 
-    fn to_image_buffer_rgb565(&self) -> Option<RgbaImage> {
-        if self.format.depth != 16 || self.format.bits_per_pixel != 16 {
-            return None;
+    /// Get the window that has the focus, from the window manager's `_NET_ACTIVE_WINDOW` if it
+    /// maintains one, or the input focus otherwise.
+    pub fn get_active_window(&self) -> Option<xproto::Window> {
+        if let Some(active) = self.intern_atom("_NET_ACTIVE_WINDOW") {
+            let cookie = self
+                .conn
+                .get_property(false, self.root(), active, xproto::AtomEnum::WINDOW, 0, 1)
+                .ok()?;
+            let reply = cookie.reply().ok()?;
+            let window = reply.value32().and_then(|mut w| w.next());
+            if let Some(w) = window.filter(|&w| w != x11rb::NONE) {
+                return Some(w);
+            }
         }
-        let bytes_per_pixel = 2;
 
-        let pad = match self.format.scanline_pad {
-            p @ (16 | 32) => p as u32 / 8,
-            _ => return None,
-        };
-        let bytes_per_line = (self.w * bytes_per_pixel + pad - 1) / pad * pad;
+        // Without a window, the focus is None or PointerRoot
+        const POINTER_ROOT: xproto::Window = 1;
+        let focus = self.conn.get_input_focus().ok()?.reply().ok()?.focus;
+        if focus == x11rb::NONE || focus == POINTER_ROOT {
+            None
+        } else {
+            Some(focus)
+        }
+    }
 
-        // Finally, generate the image object
-        Some(RgbaImage::from_fn(self.w, self.h, |x, y| {
-            let offset = (y * bytes_per_line + x * bytes_per_pixel) as usize;
-            let pixel_slice = [self.data[offset], self.data[offset + 1]];
-            let pixel = if self.byte_order == xproto::ImageOrder::LSB_FIRST {
-                u16::from_le_bytes(pixel_slice)
-            } else {
-                u16::from_be_bytes(pixel_slice)
-            };
-            let red = (pixel >> 11) & 0x1F;
-            let green = (pixel >> 5) & 0x3F;
-            let blue = pixel & 0x1F;
+    /// List the top-level client windows, from the window manager's `_NET_CLIENT_LIST` if it
+    /// maintains one, or the children of the root window otherwise.
+    pub fn get_client_windows(&self) -> Option<Vec<xproto::Window>> {
+        if let Some(client_list) = self.intern_atom("_NET_CLIENT_LIST") {
+            let cookie = self
+                .conn
+                .get_property(
+                    false,
+                    self.root(),
+                    client_list,
+                    xproto::AtomEnum::WINDOW,
+                    0,
+                    u32::MAX,
+                )
+                .ok()?;
+            let reply = cookie.reply().ok()?;
+            let windows = reply.value32().map(|w| w.collect());
+            if windows.is_some() {
+                return windows;
+            }
+        }
+
+        self.get_children(self.root())
+    }
+
+    /// Get the window that `window` is a dialog of, from its `WM_TRANSIENT_FOR` property.
+    pub fn get_transient_for(&self, window: xproto::Window) -> Option<xproto::Window> {
+        let cookie = self
+            .conn
+            .get_property(
+                false,
+                window,
+                xproto::AtomEnum::WM_TRANSIENT_FOR,
+                xproto::AtomEnum::WINDOW,
+                0,
+                1,
+            )
+            .ok()?;
+        let reply = cookie.reply().ok()?;
+        let parent = reply.value32()?.next();
+        parent.filter(|&w| w != x11rb::NONE)
+    }
+
+    /// Get the top-level ancestor of `window`, which is the frame a reparenting window manager
+    /// draws the decorations in, or `window` itself without one.
+    pub fn get_frame_window(&self, window: xproto::Window) -> Option<xproto::Window> {
+        let mut window = window;
+        loop {
+            let parent = self.conn.query_tree(window).ok()?.reply().ok()?.parent;
+            if parent == self.root() || parent == x11rb::NONE {
+                return Some(window);
+            }
+            window = parent;
+        }
+    }
+
+    /// Get the size of the decorations around `window` from its `_NET_FRAME_EXTENTS` property,
+    /// as left, right, top and bottom.
+    pub fn get_frame_extents(&self, window: xproto::Window) -> Option<[u32; 4]> {
+        let atom = self.intern_atom("_NET_FRAME_EXTENTS")?;
+        let cookie = self
+            .conn
+            .get_property(false, window, atom, xproto::AtomEnum::CARDINAL, 0, 4)
+            .ok()?;
+        let reply = cookie.reply().ok()?;
+        let extents: Vec<u32> = reply.value32()?.collect();
+        match extents[..] {
+            [left, right, top, bottom] => Some([left, right, top, bottom]),
+            _ => None,
+        }
+    }
+
+    /// List the direct children of `window`.
+    pub fn get_children(&self, window: xproto::Window) -> Option<Vec<xproto::Window>> {
+        let cookie = self.conn.query_tree(window).ok()?;
+        Some(cookie.reply().ok()?.children)
+    }
+
+    /// Get the instance and class names of a window from its `WM_CLASS` property.
+    pub fn get_wm_class(&self, window: xproto::Window) -> Option<(String, String)> {
+        let cookie = self
+            .conn
+            .get_property(
+                false,
+                window,
+                xproto::AtomEnum::WM_CLASS,
+                xproto::AtomEnum::STRING,
+                0,
+                u32::MAX,
+            )
+            .ok()?;
+        let reply = cookie.reply().ok()?;
+
+        // Two NUL-terminated strings
+        let mut parts = reply
+            .value
+            .split(|&b| b == 0)
+            .map(|p| String::from_utf8_lossy(p).into_owned());
+        Some((parts.next()?, parts.next()?))
+    }
+
+    /// Get the title of a window, from `_NET_WM_NAME` (UTF-8) or else `WM_NAME` (Latin-1).
+    pub fn get_window_name(&self, window: xproto::Window) -> Option<String> {
+        if let (Some(net_wm_name), Some(utf8_string)) = (
+            self.intern_atom("_NET_WM_NAME"),
+            self.intern_atom("UTF8_STRING"),
+        ) {
+            let cookie = self
+                .conn
+                .get_property(false, window, net_wm_name, utf8_string, 0, u32::MAX)
+                .ok()?;
+            let reply = cookie.reply().ok()?;
+            if !reply.value.is_empty() {
+                return Some(String::from_utf8_lossy(&reply.value).into_owned());
+            }
+        }
+
+        let cookie = self
+            .conn
+            .get_property(
+                false,
+                window,
+                xproto::AtomEnum::WM_NAME,
+                xproto::AtomEnum::STRING,
+                0,
+                u32::MAX,
+            )
+            .ok()?;
+        let reply = cookie.reply().ok()?;
+        if reply.value.is_empty() {
+            return None;
+        }
+        Some(latin1_to_string(&reply.value))
+    }
+
+    /// List the windows below the root along with their depth in the tree (1 for top-level
+    /// windows), depth first and in stacking order from the bottom. Unless `all` is set, only
+    /// viewable windows are listed, which leaves out the children of unmapped windows as well.
+    pub fn get_window_tree(&self, all: bool) -> Option<Vec<(usize, xproto::Window)>> {
+        let mut tree = Vec::new();
+        let mut pending: Vec<_> = self
+            .get_children(self.root())?
+            .into_iter()
+            .map(|w| (1, w))
+            .collect();
+        pending.reverse();
+        while let Some((depth, window)) = pending.pop() {
+            if !all {
+                let attributes = self.conn.get_window_attributes(window).ok()?.reply();
+                // Windows can disappear while we look at them
+                match attributes {
+                    Ok(a) if a.map_state == xproto::MapState::VIEWABLE => {}
+                    _ => continue,
+                }
+            }
+            tree.push((depth, window));
+
+            let children = self.get_children(window).unwrap_or_default();
+            pending.extend(children.into_iter().rev().map(|w| (depth + 1, w)));
+        }
+        Some(tree)
+    }
+
+    /// Find the viewable windows whose title, instance or class name contains `pattern`
+    /// (ignoring case), or is exactly `pattern` if `exact` is set. Top-level client windows come
+    /// first, from the top of the stack, then any other window roughly from the top.
+    pub fn find_windows_by_name(&self, pattern: &str, exact: bool) -> Option<Vec<xproto::Window>> {
+        let viewable: Vec<_> = self
+            .get_window_tree(false)?
+            .into_iter()
+            .map(|(_, w)| w)
+            .collect();
+        let clients: Vec<_> = self
+            .get_stacked_client_windows()?
+            .into_iter()
+            .rev()
+            .filter(|w| viewable.contains(w))
+            .collect();
+        // Later subtrees are stacked above earlier ones
+        let others = viewable.iter().rev().filter(|w| !clients.contains(w));
+
+        let mut found = Vec::new();
+        for &window in clients.iter().chain(others) {
+            let mut names: Vec<String> = self.get_window_name(window).into_iter().collect();
+            if let Some((instance, class)) = self.get_wm_class(window) {
+                names.push(instance);
+                names.push(class);
+            }
+            if names.iter().any(|n| name_matches(n, pattern, exact)) {
+                found.push(window);
+            }
+        }
+        Some(found)
+    }
+
+    /// List the top-level client windows from the bottom of the stack to the top, from the
+    /// window manager's `_NET_CLIENT_LIST_STACKING` if it maintains one, or like
+    /// [`get_client_windows`](Display::get_client_windows) otherwise.
+    fn get_stacked_client_windows(&self) -> Option<Vec<xproto::Window>> {
+        if let Some(client_list) = self.intern_atom("_NET_CLIENT_LIST_STACKING") {
+            let cookie = self
+                .conn
+                .get_property(
+                    false,
+                    self.root(),
+                    client_list,
+                    xproto::AtomEnum::WINDOW,
+                    0,
+                    u32::MAX,
+                )
+                .ok()?;
+            let reply = cookie.reply().ok()?;
+            let windows = reply.value32().map(|w| w.collect());
+            if windows.is_some() {
+                return windows;
+            }
+        }
+
+        self.get_client_windows()
+    }
+
+    /// Get the name of the current cursor shape (e.g. `left_ptr`), if it has one.
+    pub fn get_cursor_name(&self) -> Option<String> {
+        // XFixes requires clients to announce the version they support before using it
+        let cookie = self.conn.xfixes_query_version(2, 0).ok()?;
+        let version = cookie.reply().ok()?;
+        if version.major_version < 2 {
+            return None;
+        }
+
+        let cookie = self.conn.xfixes_get_cursor_image_and_name().ok()?;
+        let cursor = cookie.reply().ok()?;
+        if cursor.name.is_empty() {
+            return None;
+        }
+
+        Some(String::from_utf8_lossy(&cursor.name).into_owned())
+    }
+
+    /// Get the image of the cursor, along with the position of the pointer on the screen and the
+    /// hotspot, the pixel of the image that sits at that position.
+    pub fn get_cursor_image(&self) -> Option<(util::Point, util::Point, RgbaImage)> {
+        // GetCursorImage is part of the first version of XFixes
+        let cookie = self.conn.xfixes_query_version(1, 0).ok()?;
+        cookie.reply().ok()?;
+
+        let cookie = self.conn.xfixes_get_cursor_image().ok()?;
+        let cursor = cookie.reply().ok()?;
+        let image = cursor_image(
+            cursor.width as u32,
+            cursor.height as u32,
+            &cursor.cursor_image,
+        )?;
+        let position = util::Point {
+            x: cursor.x as i32,
+            y: cursor.y as i32,
+        };
+        let hotspot = util::Point {
+            x: cursor.xhot as i32,
+            y: cursor.yhot as i32,
+        };
+        Some((position, hotspot, image))
+    }
+
+    /// Grab the pointer and keyboard for an interactive selection, showing `glyph` of the
+    /// standard cursor font as the pointer. Returns the cursor to pass to
+    /// [`ungrab_input`](Display::ungrab_input).
+    fn grab_input(&self, glyph: u16, events: xproto::EventMask) -> Option<xproto::Cursor> {
+        let font = self.conn.generate_id().ok()?;
+        self.conn.open_font(font, b"cursor").ok()?;
+        let cursor = self.conn.generate_id().ok()?;
+        self.conn
+            .create_glyph_cursor(
+                cursor,
+                font,
+                font,
+                glyph,
+                glyph + 1,
+                0,
+                0,
+                0,
+                0xffff,
+                0xffff,
+                0xffff,
+            )
+            .ok()?;
+        self.conn.close_font(font).ok()?;
+
+        let pointer = self
+            .conn
+            .grab_pointer(
+                false,
+                self.root(),
+                events,
+                xproto::GrabMode::ASYNC,
+                xproto::GrabMode::ASYNC,
+                x11rb::NONE,
+                cursor,
+                x11rb::CURRENT_TIME,
+            )
+            .ok()?
+            .reply()
+            .ok()?;
+        let keyboard = self
+            .conn
+            .grab_keyboard(
+                false,
+                self.root(),
+                x11rb::CURRENT_TIME,
+                xproto::GrabMode::ASYNC,
+                xproto::GrabMode::ASYNC,
+            )
+            .ok()?
+            .reply()
+            .ok()?;
+        if pointer.status != xproto::GrabStatus::SUCCESS
+            || keyboard.status != xproto::GrabStatus::SUCCESS
+        {
+            self.ungrab_input(cursor);
+            return None;
+        }
+        Some(cursor)
+    }
+
+    fn ungrab_input(&self, cursor: xproto::Cursor) {
+        let _ = self.conn.ungrab_pointer(x11rb::CURRENT_TIME);
+        let _ = self.conn.ungrab_keyboard(x11rb::CURRENT_TIME);
+        let _ = self.conn.free_cursor(cursor);
+        let _ = self.conn.flush();
+    }
+
+    /// Find the keycodes that produce the Escape key.
+    fn escape_keycodes(&self) -> Option<Vec<xproto::Keycode>> {
+        const XK_ESCAPE: xproto::Keysym = 0xff1b;
+
+        let setup = self.conn.setup();
+        let count = setup.max_keycode - setup.min_keycode + 1;
+        let cookie = self
+            .conn
+            .get_keyboard_mapping(setup.min_keycode, count)
+            .ok()?;
+        let mapping = cookie.reply().ok()?;
+        let per_keycode = mapping.keysyms_per_keycode as usize;
+        if per_keycode == 0 {
+            return Some(Vec::new());
+        }
+
+        Some(
+            mapping
+                .keysyms
+                .chunks(per_keycode)
+                .zip(setup.min_keycode..=setup.max_keycode)
+                .filter(|(keysyms, _)| keysyms.contains(&XK_ESCAPE))
+                .map(|(_, keycode)| keycode)
+                .collect(),
+        )
+    }
+
+    /// Let the user click a window, returning the top-level window under the pointer (or the
+    /// root window), or the innermost one if `deepest` is set. Returns `None` if the selection is
+    /// cancelled with Escape or a right click.
+    pub fn select_window_by_click(&self, deepest: bool) -> Option<xproto::Window> {
+        const XC_CROSSHAIR: u16 = 34;
+        const BUTTON_LEFT: u8 = 1;
+        const BUTTON_RIGHT: u8 = 3;
+
+        let escape = self.escape_keycodes()?;
+        let cursor = self.grab_input(XC_CROSSHAIR, xproto::EventMask::BUTTON_PRESS)?;
+
+        let selected = loop {
+            let event = match self.conn.wait_for_event() {
+                Ok(e) => e,
+                Err(_) => break None,
+            };
+            match event {
+                x11rb::protocol::Event::ButtonPress(press) if press.detail == BUTTON_LEFT => {
+                    if deepest {
+                        break self.window_at_point(util::Point {
+                            x: press.root_x as i32,
+                            y: press.root_y as i32,
+                        });
+                    }
+                    break Some(if press.child == x11rb::NONE {
+                        self.root()
+                    } else {
+                        press.child
+                    });
+                }
+                x11rb::protocol::Event::ButtonPress(press) if press.detail == BUTTON_RIGHT => {
+                    break None;
+                }
+                x11rb::protocol::Event::KeyPress(key) if escape.contains(&key.detail) => {
+                    break None;
+                }
+                _ => {}
+            }
+        };
+
+        self.ungrab_input(cursor);
+        selected
+    }
+
+    /// Let the user drag a rectangle over the screen with the left button, returning it in root
+    /// window coordinates. Returns `None` if the selection is cancelled with Escape or a right
+    /// click.
+    pub fn select_region(&self) -> Option<util::Rect> {
+        const XC_CROSSHAIR: u16 = 34;
+        const BUTTON_LEFT: u8 = 1;
+        const BUTTON_RIGHT: u8 = 3;
+
+        let escape = self.escape_keycodes()?;
+
+        // Drawing with XOR on the root window, over its children, makes erasing the rubber band
+        // a matter of drawing it again
+        let gc = self.conn.generate_id().ok()?;
+        let screen = self.screen();
+        self.conn
+            .create_gc(
+                gc,
+                self.root(),
+                &xproto::CreateGCAux::new()
+                    .function(xproto::GX::XOR)
+                    .foreground(screen.white_pixel ^ screen.black_pixel)
+                    .subwindow_mode(xproto::SubwindowMode::INCLUDE_INFERIORS),
+            )
+            .ok()?;
+        let draw = |rect: util::Rect| {
+            let _ = self.conn.poly_rectangle(
+                self.root(),
+                gc,
+                &[xproto::Rectangle {
+                    x: rect.x as i16,
+                    y: rect.y as i16,
+                    width: (rect.w - 1) as u16,
+                    height: (rect.h - 1) as u16,
+                }],
+            );
+            let _ = self.conn.flush();
+        };
+
+        let events = xproto::EventMask::BUTTON_PRESS
+            | xproto::EventMask::BUTTON_RELEASE
+            | xproto::EventMask::POINTER_MOTION;
+        let cursor = match self.grab_input(XC_CROSSHAIR, events) {
+            Some(c) => c,
+            None => {
+                let _ = self.conn.free_gc(gc);
+                return None;
+            }
+        };
+
+        let point = |x: i16, y: i16| util::Point {
+            x: x as i32,
+            y: y as i32,
+        };
+        let mut start = None;
+        let mut drawn: Option<util::Rect> = None;
+        let selected = loop {
+            let event = match self.conn.wait_for_event() {
+                Ok(e) => e,
+                Err(_) => break None,
+            };
+            match event {
+                x11rb::protocol::Event::ButtonPress(press) if press.detail == BUTTON_LEFT => {
+                    start = Some(point(press.root_x, press.root_y));
+                }
+                x11rb::protocol::Event::MotionNotify(motion) => {
+                    if let Some(start) = start {
+                        let rect =
+                            util::Rect::from_corners(start, point(motion.root_x, motion.root_y));
+                        if let Some(old) = drawn.replace(rect) {
+                            draw(old);
+                        }
+                        draw(rect);
+                    }
+                }
+                x11rb::protocol::Event::ButtonRelease(release) if release.detail == BUTTON_LEFT => {
+                    if let Some(start) = start {
+                        let end = point(release.root_x, release.root_y);
+                        break Some(util::Rect::from_corners(start, end));
+                    }
+                }
+                x11rb::protocol::Event::ButtonPress(press) if press.detail == BUTTON_RIGHT => {
+                    break None;
+                }
+                x11rb::protocol::Event::KeyPress(key) if escape.contains(&key.detail) => {
+                    break None;
+                }
+                _ => {}
+            }
+        };
+
+        if let Some(old) = drawn {
+            draw(old);
+        }
+        let _ = self.conn.free_gc(gc);
+        self.ungrab_input(cursor);
+        // Make sure the rubber band is gone before capturing
+        let _ = self.conn.sync();
+        selected
+    }
+
+    /// Cover the screen with a still copy of it, `image` being a capture of the whole root window,
+    /// so that nothing appears to move until the returned guard is dropped.
+    pub fn freeze(&self, image: &RgbaImage) -> Option<Frozen<'_>> {
+        let screen = self.screen();
+        let format = *self
+            .conn
+            .setup()
+            .pixmap_formats
+            .iter()
+            .find(|f| f.depth == screen.root_depth)?;
+        let visual = *self.find_visual(screen.root_visual)?;
+        let data = encode_pixels(image, format, visual, self.conn.setup().image_byte_order)?;
+        let (w, h) = (
+            u16::try_from(image.width()).ok()?,
+            u16::try_from(image.height()).ok()?,
+        );
+
+        let pixmap = self.conn.generate_id().ok()?;
+        self.conn
+            .create_pixmap(screen.root_depth, pixmap, self.root(), w, h)
+            .ok()?;
+        let gc = self.conn.generate_id().ok()?;
+        self.conn.create_gc(gc, pixmap, &Default::default()).ok()?;
+
+        // Upload in bands of rows that fit in a request
+        let stride = image.width() as usize * 4;
+        let max_rows = ((self.conn.maximum_request_bytes() - 64) / stride).max(1);
+        for (i, band) in data.chunks(max_rows * stride).enumerate() {
+            self.conn
+                .put_image(
+                    xproto::ImageFormat::Z_PIXMAP,
+                    pixmap,
+                    gc,
+                    w,
+                    (band.len() / stride) as u16,
+                    0,
+                    (i * max_rows) as i16,
+                    0,
+                    screen.root_depth,
+                    band,
+                )
+                .ok()?;
+        }
+        let _ = self.conn.free_gc(gc);
+
+        // Override-redirect keeps the window manager from decorating or moving the window
+        let window = self.conn.generate_id().ok()?;
+        self.conn
+            .create_window(
+                screen.root_depth,
+                window,
+                self.root(),
+                0,
+                0,
+                w,
+                h,
+                0,
+                xproto::WindowClass::INPUT_OUTPUT,
+                screen.root_visual,
+                &xproto::CreateWindowAux::new()
+                    .override_redirect(1)
+                    .background_pixmap(pixmap),
+            )
+            .ok()?;
+        let frozen = Frozen {
+            display: self,
+            window,
+            pixmap,
+        };
+        self.conn.map_window(window).ok()?;
+        self.conn.sync().ok()?;
+        Some(frozen)
+    }
+
+    /// Own the `CLIPBOARD` selection and hand `data` out as `mime_type` to the clients that ask
+    /// for it, until another client takes the clipboard or `timeout` runs out.
+    pub fn serve_clipboard(
+        &self,
+        data: &[u8],
+        mime_type: &str,
+        timeout: Option<time::Duration>,
+    ) -> Option<()> {
+        let clipboard = self.create_atom("CLIPBOARD")?;
+        let targets = self.create_atom("TARGETS")?;
+        let incr = self.create_atom("INCR")?;
+        let mime = self.create_atom(mime_type)?;
+
+        let window = self.conn.generate_id().ok()?;
+        self.conn
+            .create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                window,
+                self.root(),
+                0,
+                0,
+                1,
+                1,
+                0,
+                xproto::WindowClass::INPUT_ONLY,
+                x11rb::COPY_FROM_PARENT,
+                &xproto::CreateWindowAux::new(),
+            )
+            .ok()?;
+        self.conn
+            .set_selection_owner(window, clipboard, x11rb::CURRENT_TIME)
+            .ok()?;
+        let owner = self
+            .conn
+            .get_selection_owner(clipboard)
+            .ok()?
+            .reply()
+            .ok()?;
+        if owner.owner != window {
+            return None;
+        }
+
+        // Leave room for the request header
+        let max_size = self.conn.maximum_request_bytes() - 1024;
+        let deadline = timeout.map(|t| time::Instant::now() + t);
+        loop {
+            let event = match deadline {
+                None => self.conn.wait_for_event().ok()?,
+                Some(deadline) => loop {
+                    if let Some(event) = self.conn.poll_for_event().ok()? {
+                        break event;
+                    }
+                    if time::Instant::now() >= deadline {
+                        return Some(());
+                    }
+                    thread::sleep(CLIPBOARD_POLL_INTERVAL);
+                },
+            };
+            let request = match event {
+                x11rb::protocol::Event::SelectionRequest(r) => r,
+                // Someone else copied something
+                x11rb::protocol::Event::SelectionClear(_) => return Some(()),
+                _ => continue,
+            };
+
+            // Obsolete clients don't give a property, ICCCM says to use the target then
+            let property = if request.property == x11rb::NONE {
+                request.target
+            } else {
+                request.property
+            };
+            let mut incremental = false;
+            let reply_property = if request.target == targets {
+                self.conn
+                    .change_property32(
+                        xproto::PropMode::REPLACE,
+                        request.requestor,
+                        property,
+                        xproto::AtomEnum::ATOM,
+                        &[targets, mime],
+                    )
+                    .ok()?;
+                property
+            } else if request.target == mime && data.len() <= max_size {
+                self.conn
+                    .change_property8(
+                        xproto::PropMode::REPLACE,
+                        request.requestor,
+                        property,
+                        mime,
+                        data,
+                    )
+                    .ok()?;
+                property
+            } else if request.target == mime {
+                // Too large for a single request, announce an INCR transfer
+                self.conn
+                    .change_window_attributes(
+                        request.requestor,
+                        &xproto::ChangeWindowAttributesAux::new()
+                            .event_mask(xproto::EventMask::PROPERTY_CHANGE),
+                    )
+                    .ok()?;
+                self.conn
+                    .change_property32(
+                        xproto::PropMode::REPLACE,
+                        request.requestor,
+                        property,
+                        incr,
+                        &[u32::try_from(data.len()).unwrap_or(u32::MAX)],
+                    )
+                    .ok()?;
+                incremental = true;
+                property
+            } else {
+                x11rb::NONE
+            };
+
+            let notify = xproto::SelectionNotifyEvent {
+                response_type: xproto::SELECTION_NOTIFY_EVENT,
+                sequence: 0,
+                time: request.time,
+                requestor: request.requestor,
+                selection: request.selection,
+                target: request.target,
+                property: reply_property,
+            };
+            self.conn
+                .send_event(
+                    false,
+                    request.requestor,
+                    xproto::EventMask::NO_EVENT,
+                    notify,
+                )
+                .ok()?;
+            self.conn.flush().ok()?;
+
+            if incremental {
+                self.send_incremental(request.requestor, property, mime, data)?;
+            }
+        }
+    }
+
+    /// Send `data` in chunks, each time the requestor deletes `property` to ask for the next one,
+    /// ending with an empty chunk.
+    fn send_incremental(
+        &self,
+        requestor: xproto::Window,
+        property: xproto::Atom,
+        mime: xproto::Atom,
+        data: &[u8],
+    ) -> Option<()> {
+        for chunk in data
+            .chunks(CLIPBOARD_CHUNK_SIZE)
+            .chain(std::iter::once(&[][..]))
+        {
+            loop {
+                match self.conn.wait_for_event().ok()? {
+                    x11rb::protocol::Event::PropertyNotify(e)
+                        if e.window == requestor
+                            && e.atom == property
+                            && e.state == xproto::Property::DELETE =>
+                    {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            self.conn
+                .change_property8(xproto::PropMode::REPLACE, requestor, property, mime, chunk)
+                .ok()?;
+            self.conn.flush().ok()?;
+        }
+
+        self.conn
+            .change_window_attributes(
+                requestor,
+                &xproto::ChangeWindowAttributesAux::new().event_mask(xproto::EventMask::NO_EVENT),
+            )
+            .ok()?;
+        self.conn.flush().ok()
+    }
+
+    /// Get the top-level window under the cursor. With a reparenting window manager, this is the
+    /// frame window, decorations included.
+    pub fn get_window_under_cursor(&self) -> Option<xproto::Window> {
+        let cookie = self.conn.query_pointer(self.root()).ok()?;
+        let pointer = cookie.reply().ok()?;
+
+        if pointer.child == x11rb::NONE {
+            None
+        } else {
+            Some(pointer.child)
+        }
+    }
+
+    /// Get the innermost mapped window containing `point` (in root window coordinates), going
+    /// down through the children of each window that contains it.
+    pub fn window_at_point(&self, point: util::Point) -> Option<xproto::Window> {
+        let mut window = self.root();
+        loop {
+            let cookie = self
+                .conn
+                .translate_coordinates(self.root(), window, point.x as i16, point.y as i16)
+                .ok()?;
+            let child = cookie.reply().ok()?.child;
+            if child == x11rb::NONE {
+                return Some(window);
+            }
+            window = child;
+        }
+    }
+
+    pub fn get_cursor_position(&self) -> Option<util::Point> {
+        let cookie = self.conn.query_pointer(self.root()).ok()?;
+        let pointer = cookie.reply().ok()?;
+
+        Some(util::Point {
+            x: pointer.win_x as i32,
+            y: pointer.win_y as i32,
+        })
+    }
+}
+
+/// Build an image from 32-bit pixels in `order`, whatever their visual says.
+fn forced_image(
+    rect: util::Rect,
+    img: xproto::GetImageReply,
+    format: xproto::Format,
+    order: ChannelOrder,
+) -> Option<Image> {
+    if format.bits_per_pixel != 32 {
+        return None;
+    }
+
+    // Masks of the channels in little-endian pixel values
+    let (red_mask, blue_mask) = match order {
+        ChannelOrder::Rgba => (0xff, 0xff0000),
+        ChannelOrder::Bgra => (0xff0000, 0xff),
+    };
+    Some(Image {
+        w: rect.w as u32,
+        h: rect.h as u32,
+        format: xproto::Format {
+            // Anything but 32 would be taken as a depth without alpha that we can't read
+            depth: if img.depth == 32 { 32 } else { 24 },
+            ..format
+        },
+        visual: xproto::Visualtype {
+            visual_id: x11rb::NONE,
+            class: xproto::VisualClass::TRUE_COLOR,
+            bits_per_rgb_value: 8,
+            colormap_entries: 256,
+            red_mask,
+            green_mask: 0xff00,
+            blue_mask,
+        },
+        byte_order: xproto::ImageOrder::LSB_FIRST,
+        data: img.data,
+    })
+}
+
+/// Convert `image` to 32-bit pixels laid out as the server expects for `format` and `visual`, the
+/// reverse of [`Image::to_image_buffer`]. Alpha is dropped.
+fn encode_pixels(
+    image: &RgbaImage,
+    format: xproto::Format,
+    visual: xproto::Visualtype,
+    byte_order: xproto::ImageOrder,
+) -> Option<Vec<u8>> {
+    if format.bits_per_pixel != 32 || visual.class != xproto::VisualClass::TRUE_COLOR {
+        return None;
+    }
+
+    // Scale an 8-bit channel to the width of the mask, and move it into place
+    let channel = |value: u8, mask: u32| {
+        let shift = mask.trailing_zeros();
+        let max = mask.checked_shr(shift).unwrap_or(0);
+        ((value as u32 * max + 127) / 255) << shift
+    };
+    let mut data = Vec::with_capacity(image.as_raw().len());
+    for &Rgba([r, g, b, _]) in image.pixels() {
+        let pixel = channel(r, visual.red_mask)
+            | channel(g, visual.green_mask)
+            | channel(b, visual.blue_mask);
+        if byte_order == xproto::ImageOrder::LSB_FIRST {
+            data.extend_from_slice(&pixel.to_le_bytes());
+        } else {
+            data.extend_from_slice(&pixel.to_be_bytes());
+        }
+    }
+    Some(data)
+}
+
+/// Decode Latin-1 text, whose bytes are the first 256 Unicode code points.
+fn latin1_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn name_matches(name: &str, pattern: &str, exact: bool) -> bool {
+    if exact {
+        name == pattern
+    } else {
+        name.to_lowercase().contains(&pattern.to_lowercase())
+    }
+}
+
+/// Convert the premultiplied ARGB pixels of an XFixes cursor image to straight RGBA.
+fn cursor_image(w: u32, h: u32, pixels: &[u32]) -> Option<RgbaImage> {
+    if pixels.len() < w as usize * h as usize {
+        return None;
+    }
+    Some(RgbaImage::from_fn(w, h, |x, y| {
+        let [b, g, r, a] = pixels[(y * w + x) as usize].to_le_bytes();
+        unpremultiply(Rgba([r, g, b, a]))
+    }))
+}
+
+/// Compute the refresh rate of a RandR mode from its timings.
+fn refresh_rate(mode: &randr::ModeInfo) -> Option<f64> {
+    let mut vtotal = mode.vtotal as f64;
+    let flags = u32::from(mode.mode_flags);
+    if flags & u32::from(randr::ModeFlag::DOUBLE_SCAN) != 0 {
+        vtotal *= 2.0;
+    }
+    if flags & u32::from(randr::ModeFlag::INTERLACE) != 0 {
+        vtotal /= 2.0;
+    }
+
+    let dots = mode.htotal as f64 * vtotal;
+    if dots == 0.0 {
+        return None;
+    }
+    Some(mode.dot_clock as f64 / dots)
+}
+
+fn unpremultiply(pixel: Rgba<u8>) -> Rgba<u8> {
+    let alpha = pixel[3] as u32;
+    if alpha == 0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+
+    let channel = |c: u8| ((c as u32 * 0xFF + alpha / 2) / alpha).min(0xFF) as u8;
+    Rgba([
+        channel(pixel[0]),
+        channel(pixel[1]),
+        channel(pixel[2]),
+        pixel[3],
+    ])
+}
+
+impl Image {
+    pub fn to_image_buffer(&self) -> Option<RgbaImage> {
+        if (
+            self.visual.red_mask,
+            self.visual.green_mask,
+            self.visual.blue_mask,
+        ) == (0xF800, 0x07E0, 0x001F)
+        {
+            return self.to_image_buffer_rgb565();
+        }
+        if self.format.depth == 30 {
+            return self.to_image_buffer_10bpc();
+        }
+
+        let bytes_per_pixel = match (self.format.depth, self.format.bits_per_pixel) {
+            (24, bpp @ 24) | (24 | 32, bpp @ 32) => bpp as u32 / 8,
+            _ => return None,
+        };
+
+        let pad = match self.format.scanline_pad {
+            p @ 32 => p as u32 / 8,
+            _ => return None,
+        };
+        let bytes_per_line = (self.w * bytes_per_pixel).div_ceil(pad) * pad;
+        if self.data.len() < (bytes_per_line * self.h) as usize {
+            return None;
+        }
+
+        let [red_offset, green_offset, blue_offset, alpha_offset] = self.channel_offsets()?;
+
+        // Finally, generate the image object
+        Some(RgbaImage::from_fn(self.w, self.h, |x, y| {
+            let offset = (y * bytes_per_line + x * bytes_per_pixel) as usize;
+            let pixel = Rgba([
+                self.data[offset + red_offset],
+                self.data[offset + green_offset],
+                self.data[offset + blue_offset],
+                // Make the alpha channel fully opaque if none is provided
+                if self.format.depth == 24 {
+                    0xFF
+                } else {
+                    self.data[offset + alpha_offset]
+                },
+            ]);
+            // ARGB visuals hold premultiplied colors, while image files expect straight alpha
+            if self.format.depth == 32 {
+                unpremultiply(pixel)
+            } else {
+                pixel
+            }
+        }))
+    }
+
+    /// Compute subpixel offsets into each pixel (red, green, blue, alpha) according the the
+    /// bitmasks X gives us.
+    fn channel_offsets(&self) -> Option<[usize; 4]> {
+        // Only 8 bit, byte-aligned values are supported
+        // Truncate masks to the lower 32 bits as that is the maximum pixel size
+        macro_rules! channel_offset {
+            ($mask:expr) => {{
+                use xproto::ImageOrder as O;
+                match (self.byte_order, $mask & 0xFFFFFFFF) {
+                    (O::LSB_FIRST, 0xFF) | (O::MSB_FIRST, 0xFF000000) => 0,
+                    (O::LSB_FIRST, 0xFF00) | (O::MSB_FIRST, 0xFF0000) => 1,
+                    (O::LSB_FIRST, 0xFF0000) | (O::MSB_FIRST, 0xFF00) => 2,
+                    (O::LSB_FIRST, 0xFF000000) | (O::MSB_FIRST, 0xFF) => 3,
+                    _ => return None,
+                }
+            }};
+        }
+        Some([
+            channel_offset!(self.visual.red_mask),
+            channel_offset!(self.visual.green_mask),
+            channel_offset!(self.visual.blue_mask),
+            channel_offset!(
+                !(self.visual.red_mask | self.visual.green_mask | self.visual.blue_mask)
+            ),
+        ])
+    }
+
+    /// Consume the image and hand out its pixels, with a descriptor of their layout.
+    ///
+    /// With 32 bits per pixel and byte-aligned channels, which is what most X servers use, the
+    /// buffer received from X is returned as is when its channels are in RGBA or BGRA order, and
+    /// reordered in place to RGBA otherwise: no copy is made. Other formats (24 bits per pixel,
+    /// RGB565, deep color) are converted by [`Image::to_image_buffer`], which copies them once.
+    pub fn into_raw(mut self) -> Option<(Vec<u8>, RawFormat)> {
+        let len = self.w as usize * self.h as usize * 4;
+        let offsets = match self.channel_offsets() {
+            Some(o)
+                if self.format.bits_per_pixel == 32
+                    && matches!(self.format.depth, 24 | 32)
+                    && self.data.len() >= len =>
+            {
+                o
+            }
+            _ => {
+                let image = self.to_image_buffer()?;
+                let format = RawFormat {
+                    width: self.w,
+                    height: self.h,
+                    order: ChannelOrder::Rgba,
+                    alpha: true,
+                    premultiplied: false,
+                };
+                return Some((image.into_raw(), format));
+            }
+        };
+
+        self.data.truncate(len);
+        let order = match offsets {
+            [0, 1, 2, 3] => ChannelOrder::Rgba,
+            [2, 1, 0, 3] => ChannelOrder::Bgra,
+            [red_offset, green_offset, blue_offset, alpha_offset] => {
+                for subpixels in self.data.chunks_exact_mut(4) {
+                    let pixel = [
+                        subpixels[red_offset],
+                        subpixels[green_offset],
+                        subpixels[blue_offset],
+                        subpixels[alpha_offset],
+                    ];
+                    subpixels.copy_from_slice(&pixel);
+                }
+                ChannelOrder::Rgba
+            }
+        };
+        let format = RawFormat {
+            width: self.w,
+            height: self.h,
+            order,
+            alpha: self.format.depth == 32,
+            // ARGB visuals hold premultiplied colors
+            premultiplied: self.format.depth == 32,
+        };
+        Some((self.data, format))
+    }
+
+    /// Same as `to_image_buffer`, but reuses the buffer received from X where
+    /// [`Image::into_raw`] can.
+    pub fn into_image_buffer(self) -> Option<RgbaImage> {
+        let (mut data, format) = self.into_raw()?;
+        for subpixels in data.chunks_exact_mut(4) {
+            if format.order == ChannelOrder::Bgra {
+                subpixels.swap(0, 2);
+            }
+            // Make the alpha channel fully opaque if none is provided
+            if !format.alpha {
+                subpixels[3] = 0xFF;
+            }
+            if format.premultiplied {
+                let pixel = unpremultiply(Rgba([
+                    subpixels[0],
+                    subpixels[1],
+                    subpixels[2],
+                    subpixels[3],
+                ]));
+                subpixels.copy_from_slice(&pixel.0);
+            }
+        }
+        RgbaImage::from_raw(format.width, format.height, data)
+    }
+
+    /// Convert deep color (10 bits per channel, e.g. `x2r10g10b10`) down to 8 bits per channel.
+    fn to_image_buffer_10bpc(&self) -> Option<RgbaImage> {
+        if self.format.bits_per_pixel != 32 {
+            return None;
+        }
+        let bytes_per_pixel = 4;
+
+        let pad = match self.format.scanline_pad {
+            p @ 32 => p as u32 / 8,
+            _ => return None,
+        };
+        let bytes_per_line = (self.w * bytes_per_pixel).div_ceil(pad) * pad;
+        if self.data.len() < (bytes_per_line * self.h) as usize {
+            return None;
+        }
+
+        // Shift of each channel, which must be 10 contiguous bits
+        let shift = |mask: u32| {
+            let shift = mask.trailing_zeros();
+            (mask >> shift == 0x3FF).then_some(shift)
+        };
+        let red_shift = shift(self.visual.red_mask)?;
+        let green_shift = shift(self.visual.green_mask)?;
+        let blue_shift = shift(self.visual.blue_mask)?;
+
+        // Finally, generate the image object
+        Some(RgbaImage::from_fn(self.w, self.h, |x, y| {
+            let offset = (y * bytes_per_line + x * bytes_per_pixel) as usize;
+            let pixel_slice = [
+                self.data[offset],
+                self.data[offset + 1],
+                self.data[offset + 2],
+                self.data[offset + 3],
+            ];
+            let pixel = if self.byte_order == xproto::ImageOrder::LSB_FIRST {
+                u32::from_le_bytes(pixel_slice)
+            } else {
+                u32::from_be_bytes(pixel_slice)
+            };
+            // Keep the 8 most significant bits of each channel
+            let channel = |shift: u32| ((pixel >> shift & 0x3FF) >> 2) as u8;
+            Rgba([
+                channel(red_shift),
+                channel(green_shift),
+                channel(blue_shift),
+                0xFF,
+            ])
+        }))
+    }
+
+    fn to_image_buffer_rgb565(&self) -> Option<RgbaImage> {
+        if self.format.depth != 16 || self.format.bits_per_pixel != 16 {
+            return None;
+        }
+        let bytes_per_pixel = 2;
+
+        let pad = match self.format.scanline_pad {
+            p @ (16 | 32) => p as u32 / 8,
+            _ => return None,
+        };
+        let bytes_per_line = (self.w * bytes_per_pixel).div_ceil(pad) * pad;
+        if self.data.len() < (bytes_per_line * self.h) as usize {
+            return None;
+        }
+
+        // Finally, generate the image object
+        Some(RgbaImage::from_fn(self.w, self.h, |x, y| {
+            let offset = (y * bytes_per_line + x * bytes_per_pixel) as usize;
+            let pixel_slice = [self.data[offset], self.data[offset + 1]];
+            let pixel = if self.byte_order == xproto::ImageOrder::LSB_FIRST {
+                u16::from_le_bytes(pixel_slice)
+            } else {
+                u16::from_be_bytes(pixel_slice)
+            };
+            let red = (pixel >> 11) & 0x1F;
+            let green = (pixel >> 5) & 0x3F;
+            let blue = pixel & 0x1F;
             Rgba([
                 (red << 3 | red >> 2) as u8,
                 (green << 2 | green >> 4) as u8,
@@ -248,3 +2010,291 @@ impl Image {
         }))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn argb_image(w: u32, h: u32, byte_order: xproto::ImageOrder, data: Vec<u8>) -> Image {
+        Image {
+            w,
+            h,
+            format: xproto::Format {
+                depth: 32,
+                bits_per_pixel: 32,
+                scanline_pad: 32,
+            },
+            visual: xproto::Visualtype {
+                visual_id: 0,
+                class: xproto::VisualClass::TRUE_COLOR,
+                bits_per_rgb_value: 8,
+                colormap_entries: 256,
+                red_mask: 0xFF0000,
+                green_mask: 0x00FF00,
+                blue_mask: 0x0000FF,
+            },
+            byte_order,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_argb_alpha_is_preserved() {
+        // Premultiplied 0xAARRGGBB pixels
+        let pixels: [u32; 4] = [0xFF102030, 0x80643200, 0x00000000, 0x40404040];
+        let expected = [
+            Rgba([0x10, 0x20, 0x30, 0xFF]),
+            Rgba([199, 100, 0, 0x80]),
+            Rgba([0, 0, 0, 0]),
+            Rgba([0xFF, 0xFF, 0xFF, 0x40]),
+        ];
+
+        for byte_order in [xproto::ImageOrder::LSB_FIRST, xproto::ImageOrder::MSB_FIRST] {
+            let data = pixels
+                .iter()
+                .flat_map(|p| {
+                    if byte_order == xproto::ImageOrder::LSB_FIRST {
+                        p.to_le_bytes()
+                    } else {
+                        p.to_be_bytes()
+                    }
+                })
+                .collect();
+            let image = argb_image(2, 2, byte_order, data)
+                .to_image_buffer()
+                .unwrap();
+            let converted: Vec<_> = image.pixels().copied().collect();
+            assert_eq!(converted, expected);
+        }
+    }
+
+    #[test]
+    fn test_into_image_buffer_matches_copy() {
+        let pixels: [u32; 4] = [0xFF102030, 0x80643200, 0x00000000, 0x40404040];
+        for byte_order in [xproto::ImageOrder::LSB_FIRST, xproto::ImageOrder::MSB_FIRST] {
+            for depth in [24, 32] {
+                let data: Vec<u8> = pixels
+                    .iter()
+                    .flat_map(|p| {
+                        if byte_order == xproto::ImageOrder::LSB_FIRST {
+                            p.to_le_bytes()
+                        } else {
+                            p.to_be_bytes()
+                        }
+                    })
+                    .collect();
+                let mut image = argb_image(2, 2, byte_order, data);
+                image.format.depth = depth;
+                let copied = image.to_image_buffer().unwrap();
+                assert_eq!(image.into_image_buffer().unwrap(), copied);
+            }
+        }
+    }
+
+    #[test]
+    fn test_into_raw() {
+        let data = 0x80643200u32.to_le_bytes().to_vec();
+        let ptr = data.as_ptr();
+        let (raw, format) = argb_image(1, 1, xproto::ImageOrder::LSB_FIRST, data)
+            .into_raw()
+            .unwrap();
+        // BGRA in memory, handed out without copying
+        assert_eq!(raw.as_ptr(), ptr);
+        assert_eq!(raw, [0x00, 0x32, 0x64, 0x80]);
+        assert_eq!(format.order, ChannelOrder::Bgra);
+        assert!(format.alpha && format.premultiplied);
+
+        // ARGB in memory is reordered in place
+        let data = 0x80643200u32.to_be_bytes().to_vec();
+        let (raw, format) = argb_image(1, 1, xproto::ImageOrder::MSB_FIRST, data)
+            .into_raw()
+            .unwrap();
+        assert_eq!(raw, [0x64, 0x32, 0x00, 0x80]);
+        assert_eq!(format.order, ChannelOrder::Rgba);
+
+        // Short buffers go through the checked copy
+        let image = argb_image(2, 2, xproto::ImageOrder::LSB_FIRST, vec![0; 4]);
+        assert!(image.into_raw().is_none());
+    }
+
+    #[test]
+    fn test_10bpc() {
+        // Full, half, quarter and zero intensity in 10 bits per channel
+        let rgb = |r: u32, g: u32, b: u32| r << 20 | g << 10 | b;
+        let pixels = [rgb(0x3FF, 0x200, 0x100), rgb(0, 0x3FF, 0x003)];
+        let expected = [Rgba([0xFF, 0x80, 0x40, 0xFF]), Rgba([0, 0xFF, 0, 0xFF])];
+
+        for byte_order in [xproto::ImageOrder::LSB_FIRST, xproto::ImageOrder::MSB_FIRST] {
+            let data = pixels
+                .iter()
+                .flat_map(|p| {
+                    if byte_order == xproto::ImageOrder::LSB_FIRST {
+                        p.to_le_bytes()
+                    } else {
+                        p.to_be_bytes()
+                    }
+                })
+                .collect();
+            let mut image = argb_image(2, 1, byte_order, data);
+            image.format.depth = 30;
+            image.visual.bits_per_rgb_value = 10;
+            image.visual.red_mask = 0x3FF00000;
+            image.visual.green_mask = 0x000FFC00;
+            image.visual.blue_mask = 0x000003FF;
+            let converted: Vec<_> = image.to_image_buffer().unwrap().pixels().copied().collect();
+            assert_eq!(converted, expected);
+
+            // Same pixels with red and blue swapped
+            image.visual.red_mask = 0x000003FF;
+            image.visual.blue_mask = 0x3FF00000;
+            let converted: Vec<_> = image
+                .into_image_buffer()
+                .unwrap()
+                .pixels()
+                .copied()
+                .collect();
+            let swapped: Vec<_> = expected
+                .iter()
+                .map(|&Rgba([r, g, b, a])| Rgba([b, g, r, a]))
+                .collect();
+            assert_eq!(converted, swapped);
+        }
+    }
+
+    #[test]
+    fn test_encode_pixels() {
+        let image = RgbaImage::from_vec(2, 1, vec![0x10, 0x20, 0x30, 0x80, 0xFF, 0x80, 0x00, 0xFF])
+            .unwrap();
+        for byte_order in [xproto::ImageOrder::LSB_FIRST, xproto::ImageOrder::MSB_FIRST] {
+            let mut x_image = argb_image(2, 1, byte_order, Vec::new());
+            x_image.format.depth = 24;
+            x_image.data =
+                encode_pixels(&image, x_image.format, x_image.visual, byte_order).unwrap();
+            // The round trip only loses alpha
+            let decoded = x_image.to_image_buffer().unwrap();
+            assert_eq!(decoded.get_pixel(0, 0), &Rgba([0x10, 0x20, 0x30, 0xFF]));
+            assert_eq!(decoded.get_pixel(1, 0), &Rgba([0xFF, 0x80, 0x00, 0xFF]));
+
+            // Deep color
+            x_image.format.depth = 30;
+            x_image.visual.red_mask = 0x3FF00000;
+            x_image.visual.green_mask = 0x000FFC00;
+            x_image.visual.blue_mask = 0x000003FF;
+            x_image.data =
+                encode_pixels(&image, x_image.format, x_image.visual, byte_order).unwrap();
+            let decoded = x_image.to_image_buffer().unwrap();
+            assert_eq!(decoded.get_pixel(1, 0), &Rgba([0xFF, 0x80, 0x00, 0xFF]));
+        }
+
+        let format = xproto::Format {
+            depth: 16,
+            bits_per_pixel: 16,
+            scanline_pad: 32,
+        };
+        let visual = argb_image(1, 1, xproto::ImageOrder::LSB_FIRST, Vec::new()).visual;
+        assert!(encode_pixels(&image, format, visual, xproto::ImageOrder::LSB_FIRST).is_none());
+    }
+
+    #[test]
+    fn test_forced_image() {
+        let rect = util::Rect {
+            x: 0,
+            y: 0,
+            w: 1,
+            h: 1,
+        };
+        // A 30-bit depth, which the visual-based conversion can't handle
+        let format = xproto::Format {
+            depth: 30,
+            bits_per_pixel: 32,
+            scanline_pad: 32,
+        };
+        let reply = || xproto::GetImageReply {
+            depth: 30,
+            sequence: 0,
+            visual: 0,
+            data: vec![0x10, 0x20, 0x30, 0x00],
+        };
+
+        let rgba = forced_image(rect, reply(), format, ChannelOrder::Rgba).unwrap();
+        let image = rgba.to_image_buffer().unwrap();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0x10, 0x20, 0x30, 0xFF]));
+
+        let bgra = forced_image(rect, reply(), format, ChannelOrder::Bgra).unwrap();
+        let image = bgra.to_image_buffer().unwrap();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0x30, 0x20, 0x10, 0xFF]));
+
+        let format = xproto::Format {
+            bits_per_pixel: 24,
+            ..format
+        };
+        assert!(forced_image(rect, reply(), format, ChannelOrder::Rgba).is_none());
+    }
+
+    #[test]
+    fn test_latin1_to_string() {
+        assert_eq!(latin1_to_string(b"caf\xe9"), "café");
+        assert_eq!(latin1_to_string(b""), "");
+    }
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("Mozilla Firefox", "firefox", false));
+        assert!(name_matches("ÉDITEUR", "éditeur", false));
+        assert!(!name_matches("Mozilla Firefox", "chromium", false));
+        assert!(name_matches("Firefox", "Firefox", true));
+        assert!(!name_matches("Mozilla Firefox", "Firefox", true));
+        assert!(!name_matches("firefox", "Firefox", true));
+    }
+
+    #[test]
+    fn test_cursor_image() {
+        let image = cursor_image(2, 1, &[0xFF102030, 0x80400000]).unwrap();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0x10, 0x20, 0x30, 0xFF]));
+        assert_eq!(image.get_pixel(1, 0), &Rgba([0x80, 0, 0, 0x80]));
+
+        assert!(cursor_image(2, 2, &[0; 3]).is_none());
+    }
+
+    #[test]
+    fn test_refresh_rate() {
+        // 1920x1080@60 CEA timings
+        let mut mode = randr::ModeInfo {
+            id: 0,
+            width: 1920,
+            height: 1080,
+            dot_clock: 148_500_000,
+            hsync_start: 2008,
+            hsync_end: 2052,
+            htotal: 2200,
+            hskew: 0,
+            vsync_start: 1084,
+            vsync_end: 1089,
+            vtotal: 1125,
+            name_len: 0,
+            mode_flags: randr::ModeFlag::default(),
+        };
+        assert_eq!(refresh_rate(&mode), Some(60.0));
+
+        mode.mode_flags = randr::ModeFlag::INTERLACE;
+        assert_eq!(refresh_rate(&mode), Some(120.0));
+
+        mode.htotal = 0;
+        assert_eq!(refresh_rate(&mode), None);
+    }
+
+    #[test]
+    fn test_is_valid_image_rect() {
+        let rect = |x, y, w, h| util::Rect { x, y, w, h };
+        assert!(is_valid_image_rect(rect(0, 0, 1920, 1080)));
+        assert!(is_valid_image_rect(rect(-10, -10, 1, 1)));
+        assert!(is_valid_image_rect(rect(0, 0, 65535, 65535)));
+
+        assert!(!is_valid_image_rect(rect(0, 0, 0, 1080)));
+        assert!(!is_valid_image_rect(rect(0, 0, 1920, 0)));
+        assert!(!is_valid_image_rect(rect(0, 0, -1, 1080)));
+        assert!(!is_valid_image_rect(rect(0, 0, 1920, -1080)));
+        assert!(!is_valid_image_rect(rect(0, 0, 65536, 1080)));
+        assert!(!is_valid_image_rect(rect(40000, 0, 1, 1)));
+    }
+}